@@ -0,0 +1,122 @@
+// run artifice operations on local files, no discord required.
+//
+// usage: artifice-cli <input> <output> <operations...>
+// operations use the same syntax as /chain, ie:
+//     artifice-cli cat.png out.png 'resize 256 | rotate 90 | caption "hi"'
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use artifice::ffmpeg_babysitter::{ffbabysit, JobControl};
+use artifice::media_helpers::{path_str, Media, TempFileHolder};
+use artifice::pipeline::{run_part, validate_part};
+use artifice::sniff::identify;
+use artifice::{ArtificeError, JobPart};
+use ffmpeg_sidecar::command::FfmpegCommand;
+
+const USAGE: &str = "usage: artifice-cli <input> <output> <operations...>
+operations use the same syntax as /chain, ie: 'resize 256 | rotate 90 | caption \"hi\"'";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+    tracing_subscriber::fmt()
+        .without_time()
+        .with_writer(std::io::stderr)
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(tracing_subscriber::filter::LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
+        .init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [input, output, operations @ ..] = args.as_slice() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+    if operations.is_empty() {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    }
+
+    match run(Path::new(input), Path::new(output), &operations.join(" ")).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            // nobody else is going to see it, so give them everything
+            eprintln!("error: {}", err.details());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(input: &Path, output: &Path, operations: &str) -> artifice::Result {
+    let part: JobPart = operations.parse().map_err(ArtificeError::Invalid)?;
+
+    // Automatically set up FFMPEG
+    ffmpeg_sidecar::download::auto_download()
+        .map_err(|err| ArtificeError::Internal(err.to_string()))?;
+
+    // go by what's in the file, not what it's called
+    let media_type = identify(input)?;
+    validate_part(&part, media_type)?;
+
+    // the input is only ever read, the temp dir here is just along for the ride.
+    let media = Media {
+        media_type,
+        file_path: TempFileHolder {
+            dir: tempfile::tempdir()?,
+            path: PathBuf::from(input),
+        },
+        output_tempfile: None,
+    };
+
+    let result = run_part(&part, media, &JobControl::default()).await?;
+    let result_file = result.output_tempfile.ok_or_else(|| {
+        ArtificeError::Internal("Operation did not produce an output file.".into())
+    })?;
+
+    if result_file.path.extension() == output.extension() {
+        std::fs::copy(&result_file.path, output)?;
+    } else {
+        // different format, let ffmpeg sort it out
+        let child = FfmpegCommand::new()
+            .input(path_str(&result_file.path)?)
+            .overwrite()
+            .output(path_str(output)?)
+            .spawn()?;
+        ffbabysit(child, &JobControl::default())?;
+    }
+    println!("{}", output.display());
+    Ok(())
+}
+
+#[tokio::test]
+async fn rotate_test() {
+    let srcpath = env!("CARGO_MANIFEST_DIR");
+    let input = PathBuf::from(format!("{}/src/test_files/bajacat.png", srcpath));
+    let dir = tempfile::tempdir().unwrap();
+    let turned = dir.path().join("turned.png");
+    let upside_down = dir.path().join("upside-down.png");
+    let back = dir.path().join("back.png");
+    let original = image::open(&input).unwrap().to_rgb8();
+
+    run(&input, &turned, "rotate 90").await.unwrap();
+    let (width, height) = original.dimensions();
+    assert_eq!(image::image_dimensions(&turned).unwrap(), (height, width));
+
+    // turned all the way around twice should land back where it started.
+    // ffmpeg builds differ in the details, so close enough is good enough
+    run(&input, &upside_down, "rotate 180").await.unwrap();
+    run(&upside_down, &back, "rotate 180").await.unwrap();
+    let back = image::open(&back).unwrap().to_rgb8();
+    assert_eq!(back.dimensions(), original.dimensions());
+    let difference: u64 = back
+        .as_raw()
+        .iter()
+        .zip(original.as_raw())
+        .map(|(a, b)| u64::from(a.abs_diff(*b)))
+        .sum();
+    let average = difference as f64 / original.as_raw().len() as f64;
+    assert!(average < 2.0, "off by {} on average", average);
+}
@@ -8,40 +8,42 @@ use glyph_brush_layout::{FontId, GlyphPositioner, SectionGeometry};
 use image::{imageops, DynamicImage, ImageBuffer, Rgba};
 
 use crate::{
-    ffmpeg_babysitter::ffbabysit,
-    media_helpers::{get_pixel_size, new_temp_media, FFprobeError, Media, MediaType},
+    ffmpeg_babysitter::{ffbabysit, spawn_limited, JobControl},
+    filter_graph::{Filter, FilterGraph, Pad},
+    media_helpers::{new_temp_media, path_str, Media},
+    probe::MediaInfo,
+    ArtificeError,
 };
 
+/// longest caption we'll draw, in characters
+pub const MAX_CAPTION_LEN: usize = 500;
+
 pub fn caption_media(
     input_text: String,
     media: Media,
+    info: &MediaInfo,
     bottom: bool,
     text_color: (u8, u8, u8),
     bg_color: (u8, u8, u8),
+    control: &JobControl,
 ) -> Result<Media, crate::Error> {
     // creates and adds a caption to every item in the media.
 
-    // are we trying to caption audio?
-
-    if media.media_type == MediaType::Audio {
-        // we cant caption audio.
-        return Err("Cannot caption a audio file.".into());
-    }
+    // audio files are turned away by the operation before we get here.
 
     // make sure the text super long
-    const MAX_LEN: usize = 500;
-    if input_text.len() > MAX_LEN {
-        return Err(format!("Caption cannot be longer than {} characters.", MAX_LEN).into());
+    if input_text.chars().count() > MAX_CAPTION_LEN {
+        return Err(ArtificeError::TooLarge(format!(
+            "Caption cannot be longer than {} characters.",
+            MAX_CAPTION_LEN
+        )));
     }
 
     // get the size of the main image, so we can determine how wide our caption needs to be
-    let (media_x_res, _media_y_res): (i64, i64) = match get_pixel_size(&media) {
-        Ok(ok) => ok,
-        Err(err) => match err {
-            FFprobeError::UnknownSize => return Err("Could not determine file dimensions.".into()),
-            FFprobeError::Other(ouch) => return Err(ouch.into()),
-        },
-    };
+    info.require_video()?;
+    let (media_x_res, _media_y_res) = info.dimensions().ok_or_else(|| {
+        ArtificeError::Unsupported("Could not determine file dimensions.".into())
+    })?;
 
     // load in the font
     // TODO: multiple font selection
@@ -117,7 +119,7 @@ pub fn caption_media(
         layout_size_height
     );
     let mut caption_image: image::ImageBuffer<Rgba<u8>, Vec<u8>> =
-        DynamicImage::new_rgba8(media_x_res.try_into().unwrap(), layout_size_height).to_rgba8();
+        DynamicImage::new_rgba8(media_x_res, layout_size_height).to_rgba8();
 
     // render each letter / glyph
     for section in final_layout {
@@ -152,7 +154,7 @@ pub fn caption_media(
 
     let bg_color: image::Rgba<u8> = image::Rgba([bg_color.0, bg_color.1, bg_color.2, 255]);
     let mut final_image = ImageBuffer::from_pixel(
-        media_x_res as u32,
+        media_x_res,
         caption_image.height() + (vertical_padding * 2) as u32,
         bg_color,
     );
@@ -179,47 +181,53 @@ pub fn caption_media(
     let temp_caption_location = new_temp_media(caption_extension);
 
     // save the image there
-    final_image.save(&temp_caption_location.path).unwrap();
+    final_image.save(&temp_caption_location.path)?;
 
     // now stack it with ffmpeg
 
     // create a temp file to store the output from _ffmpeg_
-    let ffmpeg_extension = media.file_path.path.extension().unwrap();
+    let ffmpeg_extension = media
+        .file_path
+        .path
+        .extension()
+        .ok_or_else(|| ArtificeError::Internal("Media has no file extension.".into()))?;
     let temp_ffmpeg_location = new_temp_media(ffmpeg_extension);
 
     // now stack the image with ffmpeg
 
-    // put the two paths into a vec, we need to reverse them if this is a bottom caption
-    let mut inputs: Vec<&str> = vec![
-        temp_caption_location.path.as_path().to_str().unwrap(),
-        media.file_path.path.as_path().to_str().unwrap(),
-    ];
+    // the caption always goes in first, the media second.
+    // for a bottom caption we just stack them the other way around.
+    let mut stack_order: Vec<Pad> = vec![Pad::video_input(0), Pad::video_input(1)];
 
     // is this a bottom caption?
     if bottom {
         // swap em
-        inputs.reverse();
+        stack_order.reverse();
     }
 
+    let mut graph = FilterGraph::new();
+    graph.chain(
+        stack_order,
+        vec![Filter::new("vstack").option("inputs", 2)],
+        vec![],
+    );
+
     tracing::info!("Applying caption to media...");
 
-    let output = FfmpegCommand::new()
+    let mut command = FfmpegCommand::new();
+    command
         .hwaccel(std::env::var("HW_ACCEL").unwrap_or("none".to_string()))
-        .input(inputs[0])
-        .input(inputs[1])
-        .args([
-            // stack the media
-            "-filter_complex",
-            "vstack=inputs=2",
-        ])
+        .input(path_str(&temp_caption_location.path)?)
+        .input(path_str(&media.file_path.path)?)
+        // stack the media
+        .args(graph.to_args())
         .codec_audio("copy") // copy audio codec
         //.output(tempfile_path.to_str().unwrap()) // where is it going?
-        .output(temp_ffmpeg_location.path.to_str().unwrap())
-        .spawn()
-        .unwrap(); // run that sucker
+        .output(path_str(&temp_ffmpeg_location.path)?);
+    let output = spawn_limited(&mut command)?; // run that sucker
 
     // wait for that to finish
-    ffbabysit(output)?;
+    ffbabysit(output, control)?;
     // now build our output!
     tracing::info!("Done!");
     Ok(Media {
@@ -231,7 +239,8 @@ pub fn caption_media(
 // #TODO: move testing to reduce duplication
 #[test]
 fn caption_test() {
-    use crate::media_helpers::TempFileHolder;
+    use crate::media_helpers::{MediaType, TempFileHolder};
+    use crate::operations::MediaOperation;
     use tempfile::TempDir;
     ffmpeg_sidecar::download::auto_download().unwrap();
     //get current path to src
@@ -276,13 +285,15 @@ fn caption_test() {
     for i in test_files {
         let m_type = i.media_type;
         println!("Running {}", i.file_path.path.display());
-        let caption_result = caption_media(
-            "This is a test caption.".to_string(),
-            i,
-            false,
-            (0, 0, 0),
-            (255, 255, 255),
-        );
+        // go through the operation, since that's what turns away the audio
+        let caption = crate::operations::Caption {
+            text: "This is a test caption.".to_string(),
+            bottom: false,
+        };
+        let caption_result = caption
+            .validate(m_type)
+            .and_then(|_| crate::probe::probe(&i.file_path.path))
+            .and_then(|info| caption.execute(i, &info, &JobControl::default()));
         match caption_result {
             Ok(okay) => {
                 println!(
@@ -0,0 +1,71 @@
+// stopping jobs you didn't mean to start.
+
+use poise::serenity_prelude as serenity;
+
+use crate::queue::CancelOutcome;
+use crate::{Context, Data, JobId, Result};
+
+/// custom id of the cancel button, followed by the job id
+const CANCEL_BUTTON_PREFIX: &str = "artifice-cancel:";
+
+/// the row with the cancel button that goes on a job's reply
+pub fn cancel_buttons(job: JobId) -> Vec<serenity::CreateActionRow> {
+    vec![serenity::CreateActionRow::Buttons(vec![
+        serenity::CreateButton::new(format!("{}{}", CANCEL_BUTTON_PREFIX, *job))
+            .label("Cancel")
+            .style(serenity::ButtonStyle::Danger),
+    ])]
+}
+
+/// what to tell someone after they tried to cancel something
+fn outcome_message(outcome: CancelOutcome) -> &'static str {
+    match outcome {
+        CancelOutcome::Cancelled => "Cancelled!",
+        CancelOutcome::NotYours => "That isn't your job to cancel.",
+        CancelOutcome::NotFound => "That job already finished.",
+    }
+}
+
+/// Cancel your most recent job.
+#[poise::command(slash_command, prefix_command)]
+pub async fn cancel(ctx: Context<'_>) -> Result {
+    let user = ctx.author().id;
+    let Some(job) = ctx.data().queue.latest_job_of(user) else {
+        ctx.say("You don't have any jobs running.").await?;
+        return Ok(());
+    };
+    let is_owner = ctx.framework().options().owners.contains(&user);
+    let outcome = ctx.data().queue.cancel(job, user, is_owner).await;
+    ctx.say(outcome_message(outcome)).await?;
+    Ok(())
+}
+
+/// handle someone pressing a cancel button. returns false if it wasn't one of ours.
+pub async fn handle_cancel_button(
+    ctx: &serenity::Context,
+    press: &serenity::ComponentInteraction,
+    framework: poise::FrameworkContext<'_, Data, crate::Error>,
+    data: &Data,
+) -> Result<bool> {
+    let Some(job) = press
+        .data
+        .custom_id
+        .strip_prefix(CANCEL_BUTTON_PREFIX)
+        .and_then(|id| id.parse::<u64>().ok())
+    else {
+        return Ok(false);
+    };
+    let is_owner = framework.options().owners.contains(&press.user.id);
+    let outcome = data.queue.cancel(JobId(job), press.user.id, is_owner).await;
+    press
+        .create_response(
+            ctx,
+            serenity::CreateInteractionResponse::Message(
+                serenity::CreateInteractionResponseMessage::new()
+                    .content(outcome_message(outcome))
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+    Ok(true)
+}
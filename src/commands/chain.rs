@@ -0,0 +1,51 @@
+use poise::serenity_prelude as serenity;
+
+use super::handle_job;
+use crate::media_helpers::{CustomEmoji, MediaTarget, MediaUrl, ServerImage};
+use crate::{ArtificeError, Context, Job, JobId, JobPart, Result};
+
+/// /chain and its prefix version as one command.
+/// the prefix version can't take anything after its `#[rest]`,
+/// and discord won't take optional arguments before a required one.
+pub fn chain() -> poise::Command<crate::Data, crate::Error> {
+    poise::Command {
+        prefix_action: chain_prefix().prefix_action,
+        ..chain_slash()
+    }
+}
+
+/// Run several operations on media, one after another.
+#[poise::command(slash_command, rename = "chain")]
+async fn chain_slash(
+    ctx: Context<'_>,
+    #[description = "Operations split by |, like: resize 256 | rotate 90 | caption \"hi\""]
+    operations: String,
+    #[description = "Link to the media, instead of the last thing posted"] url: Option<MediaUrl>,
+    #[description = "Custom emoji to use as the media"] emoji: Option<CustomEmoji>,
+    #[description = "Use someone's avatar as the media"] user: Option<serenity::User>,
+    #[description = "Use this server's icon or banner as the media"] server: Option<ServerImage>,
+) -> Result {
+    run_chain(
+        ctx,
+        &operations,
+        MediaTarget::from_args(url, emoji, user.as_ref(), server),
+    )
+    .await
+}
+
+/// Run several operations on media, one after another.
+#[poise::command(prefix_command, rename = "chain")]
+async fn chain_prefix(
+    ctx: Context<'_>,
+    #[description = "Operations split by |, like: resize 256 | rotate 90 | caption \"hi\""]
+    #[rest]
+    operations: String,
+) -> Result {
+    run_chain(ctx, &operations, None).await
+}
+
+async fn run_chain(ctx: Context<'_>, operations: &str, target: Option<MediaTarget<'_>>) -> Result {
+    // parse before we go looking for media, so typos fail fast
+    let part: JobPart = operations.parse().map_err(ArtificeError::Invalid)?;
+    handle_job(ctx, Job::new_chain(part, JobId(ctx.id())), target).await
+}
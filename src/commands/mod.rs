@@ -1,16 +1,24 @@
+mod cancel;
+mod chain;
 mod ping;
 mod transform;
 
-use poise::CreateReply;
+use std::sync::Arc;
+use std::time::Duration;
+
+use poise::serenity_prelude as serenity;
+use poise::{CreateReply, ReplyHandle};
 
-use crate::captions::caption_media;
 use crate::commands::ping::ping;
 use crate::job::{Job, JobId, JobType};
-use crate::media_helpers;
-use crate::media_helpers::download_media;
-use crate::media_helpers::find_media;
-use crate::media_helpers::Media;
-use crate::{Context, Result};
+use crate::journal::{Journal, JournalEntry};
+use crate::media_helpers::{download_media, DownloadLimits, MediaType};
+use crate::media_helpers::{
+    find_media, CustomEmoji, MediaTarget, MediaUrl, ServerImage, UrlAndMediaType,
+};
+use crate::pipeline::{run_job, validate_part};
+use crate::queue::{format_wait, JobQueue, JobTicket, Priority};
+use crate::{ArtificeError, Context, Result};
 
 // return the commands in this folder.
 pub fn commands() -> Vec<poise::Command<crate::Data, crate::Error>> {
@@ -20,8 +28,12 @@ pub fn commands() -> Vec<poise::Command<crate::Data, crate::Error>> {
         register(),
         caption(),
         bottom_caption(),
+        caption_this(),
+        caption_avatar(),
         transform::resize(),
         transform::rotate(),
+        chain::chain(),
+        cancel::cancel(),
     ]
 }
 
@@ -31,61 +43,367 @@ async fn register(ctx: crate::Context<'_>) -> crate::Result {
     Ok(())
 }
 
-pub async fn handle_job(ctx: Context<'_>, job: Job) -> crate::Result {
-    let mut response = ctx
+/// everything the bot needs to hear about that isn't a command
+pub async fn event_handler(
+    ctx: &serenity::Context,
+    event: &serenity::FullEvent,
+    framework: poise::FrameworkContext<'_, crate::Data, crate::Error>,
+    data: &crate::Data,
+) -> crate::Result {
+    match event {
+        serenity::FullEvent::InteractionCreate {
+            interaction: serenity::Interaction::Component(press),
+        } => {
+            cancel::handle_cancel_button(ctx, press, framework, data).await?;
+        }
+        // keep track of media as it's posted, so we don't have to go looking later
+        serenity::FullEvent::Message { new_message } => {
+            data.media_cache.message_created(new_message);
+        }
+        serenity::FullEvent::MessageUpdate { event, .. } => {
+            data.media_cache.message_updated(event);
+        }
+        serenity::FullEvent::MessageDelete {
+            channel_id,
+            deleted_message_id,
+            ..
+        } => {
+            data.media_cache.messages_deleted(*channel_id, &[*deleted_message_id]);
+        }
+        serenity::FullEvent::MessageDeleteBulk {
+            channel_id,
+            multiple_deleted_messages_ids,
+            ..
+        } => {
+            data.media_cache.messages_deleted(*channel_id, multiple_deleted_messages_ids);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// owners and anyone with one of the configured roles skip ahead
+async fn priority_of(ctx: Context<'_>) -> Priority {
+    if ctx.framework().options().owners.contains(&ctx.author().id) {
+        return Priority::High;
+    }
+    let priority_roles = &ctx.data().priority_roles;
+    if priority_roles.is_empty() {
+        return Priority::Normal;
+    }
+    match ctx.author_member().await {
+        Some(member) if member.roles.iter().any(|role| priority_roles.contains(role)) => {
+            Priority::High
+        }
+        _ => Priority::Normal,
+    }
+}
+
+/// whether a job only failed because we're shutting down, and should run again after.
+/// anything else that went wrong would just go wrong again.
+fn cut_off_by_shutdown(queue: &JobQueue, result: &crate::Result) -> bool {
+    match result {
+        Err(ArtificeError::Restarting) => true,
+        // still running when the drain ran out of time
+        Err(ArtificeError::Cancelled) => queue.is_closed(),
+        _ => false,
+    }
+}
+
+/// find media for `job` and run it, only looking at `target` if the command was pointed somewhere
+pub async fn handle_job(
+    ctx: Context<'_>,
+    job: Job,
+    target: Option<MediaTarget<'_>>,
+) -> crate::Result {
+    let priority = priority_of(ctx).await;
+    // from here on the job can be cancelled
+    let ticket = ctx
+        .data()
+        .queue
+        .ticket(job.id, Some(ctx.author().id.into()), priority)?;
+    let response = ctx
         .reply("Searching for media...".to_string())
         .await?;
-    let find_media = find_media(ctx).await?.ok_or("No media found")?;
-    response
-        .edit(ctx, CreateReply::default().content(format!("Queue Position: {}", ctx.data().queue.len().await)))
+    let job_id = job.id;
+    let result = run_discord_job(ctx, job, target, &response, &ticket, priority).await;
+    if cut_off_by_shutdown(&ctx.data().queue, &result) {
+        // cut off by a shutdown, leave it in the journal so it runs again after
+        response
+            .edit(ctx, CreateReply::default().content(RESTARTING_MESSAGE).components(vec![]))
+            .await?;
+        return Ok(());
+    }
+    // finished, failed or cancelled, there's nothing to pick back up
+    ctx.data().journal.remove(job_id).await?;
+    if ticket.control.is_cancelled() {
+        // they know, they pressed the button
+        response
+            .edit(ctx, CreateReply::default().content("Cancelled.").components(vec![]))
+            .await?;
+        return Ok(());
+    }
+    if let Err(err) = result {
+        // tell them what happened right where they're looking, and drop the cancel button
+        err.log();
+        response
+            .edit(
+                ctx,
+                CreateReply::default()
+                    .content(format!("Error: {}", err))
+                    .components(vec![]),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+async fn run_discord_job(
+    ctx: Context<'_>,
+    mut job: Job,
+    target: Option<MediaTarget<'_>>,
+    response: &ReplyHandle<'_>,
+    ticket: &JobTicket,
+    priority: Priority,
+) -> crate::Result {
+    let found_media = find_media(ctx, target).await?;
+    if found_media.is_empty() {
+        return Err(ArtificeError::Invalid("No media found.".into()));
+    }
+    // every file we found gets its own part
+    job.spread_over(found_media.len());
+    // turn away anything we can't work on before it takes up a spot in line.
+    // files we can't tell yet get checked once they're downloaded.
+    let limits = DownloadLimits::get();
+    for (part, found) in job.parts.iter().zip(&found_media) {
+        if let Some(size) = found.size {
+            limits.check_size(size)?;
+        }
+        if found.media_type != MediaType::Unknown {
+            validate_part(part, found.media_type)?;
+        }
+    }
+    // we have everything we need to start over if the bot goes down
+    ctx.data().journal.record(JournalEntry {
+        job: job.clone(),
+        media: found_media.clone(),
+        requester: ctx.author().id,
+        priority,
+        channel: ctx.channel_id(),
+        message: response.message().await?.id,
+    })
+    .await?;
+    run_found_job(
+        &Status::Reply(ctx, response),
+        &ctx.data().queue,
+        job,
+        found_media,
+        ticket,
+    )
+    .await
+}
+
+/// how often "Processing... 42%" gets updated at most
+const PROGRESS_UPDATE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// what people see when a shutdown interrupts their job
+const RESTARTING_MESSAGE: &str =
+    "Artifice is restarting, your job will pick back up once it's back.";
+
+/// somewhere to tell people how their job is going
+enum Status<'a> {
+    /// the reply to their command
+    Reply(Context<'a>, &'a ReplyHandle<'a>),
+    /// a message we sent them before restarting, we can't get at the command anymore
+    Message(&'a serenity::Http, serenity::ChannelId, serenity::MessageId),
+}
+
+impl Status<'_> {
+    async fn edit(&self, reply: CreateReply) -> crate::Result {
+        match self {
+            Status::Reply(ctx, response) => response.edit(*ctx, reply).await?,
+            Status::Message(http, channel, message) => {
+                let mut edit = serenity::EditMessage::new();
+                if let Some(content) = reply.content {
+                    edit = edit.content(content);
+                }
+                if let Some(components) = reply.components {
+                    edit = edit.components(components);
+                }
+                for attachment in reply.attachments {
+                    edit = edit.new_attachment(attachment);
+                }
+                channel.edit_message(http, *message, edit).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// wait in line, then download, process and send back media we already found
+async fn run_found_job(
+    status: &Status<'_>,
+    queue: &JobQueue,
+    job: Job,
+    found_media: Vec<UrlAndMediaType>,
+    ticket: &JobTicket,
+) -> crate::Result {
+    // let the people behind us know how long we'll be
+    let timings = queue.timings();
+    let estimate = job
+        .parts
+        .iter()
+        .zip(&found_media)
+        .map(|(part, found)| timings.estimate(part, found.media_type, found.size))
+        .sum::<Option<Duration>>();
+    queue.set_estimate(ticket, estimate);
+    let job_id = job.id;
+    let turn = queue
+        .wait_with(ticket, move |position, eta| async move {
+            let content = match eta {
+                Some(eta) if position != 0 => {
+                    format!("Queue Position: {} ({})", position, format_wait(eta))
+                }
+                _ => format!("Queue Position: {}", position),
+            };
+            status
+                .edit(
+                    CreateReply::default()
+                        .content(content)
+                        .components(cancel::cancel_buttons(job_id)),
+                )
+                .await
+        })
         .await?;
-    ctx.data().queue.wait(job.id, ctx, &mut response).await?;
-    let _permit = ctx.data().job_semaphore.acquire().await?;
-    // download the media file
-    response
-        .edit(ctx, CreateReply::default().content("Downloading..."))
+    // download the media files
+    status
+        .edit(CreateReply::default().content("Downloading..."))
         .await?;
-    let media = download_media(find_media).await?.expect("Unable to download media!");
-    response
-        .edit(ctx, CreateReply::default().content("Processing..."))
+    let mut downloads = Vec::with_capacity(found_media.len());
+    for url in found_media {
+        downloads.push(download_media(url).await?.ok_or_else(|| {
+            ArtificeError::DownloadFailed("download_media came back empty".into())
+        })?);
+    }
+    // now we know what they really are, check again
+    for (part, media) in job.parts.iter().zip(&downloads) {
+        validate_part(part, media.media_type)?;
+    }
+    status
+        .edit(CreateReply::default().content("Processing..."))
         .await?;
-    let job = job
-        .parts
-        .first()
-        .ok_or("not implemented")?
-        .subparts
-        .first()
-        .ok_or("not implemented")?
-        .to_owned();
-    let result: Media = match job {
-        JobType::Caption { text, bottom } => caption_media(text, media, bottom, (0, 0, 0), (255, 255, 255))?,
-        JobType::Resize { width, height } => media_helpers::resize_media(media, width, height)?,
-        JobType::Rotate { rotation } => media_helpers::rotate_and_flip(media, rotation).await?,
+    let mut progress = ticket.control.progress();
+    let work = run_job(job, downloads, turn, ticket.control.clone(), timings);
+    tokio::pin!(work);
+    // keep them posted while it runs, without hammering discord
+    let mut shown = 0;
+    let mut next_update = tokio::time::Instant::now() + PROGRESS_UPDATE_INTERVAL;
+    let results = loop {
+        tokio::select! {
+            results = &mut work => break results?,
+            Ok(()) = progress.changed() => {
+                let percent = (*progress.borrow_and_update() * 100.0) as u32;
+                if percent == shown || tokio::time::Instant::now() < next_update {
+                    continue;
+                }
+                status
+                    .edit(CreateReply::default().content(format!("Processing... {}%", percent)))
+                    .await?;
+                shown = percent;
+                next_update = tokio::time::Instant::now() + PROGRESS_UPDATE_INTERVAL;
+            }
+        }
     };
-    response
-        .edit(ctx, CreateReply::default().content("Uploading..."))
-        .await?;
-    response
-        .edit(
-            ctx,
-            CreateReply::default().content("Done!").attachment(
-                poise::serenity_prelude::CreateAttachment::path(
-                    result.output_tempfile.unwrap().path,
-                )
-                .await?,
-            ),
-        )
+    status
+        .edit(CreateReply::default().content("Uploading..."))
         .await?;
+    // too late to cancel now
+    let mut reply = CreateReply::default().content("Done!").components(vec![]);
+    for result in results {
+        let output = result
+            .output_tempfile
+            .ok_or_else(|| {
+                ArtificeError::Internal("Operation did not produce an output file.".into())
+            })?;
+        reply = reply.attachment(
+            poise::serenity_prelude::CreateAttachment::path(output.path).await?,
+        );
+    }
+    status.edit(reply).await?;
 
     Ok(())
 }
 
+/// start every job that was still going when we last shut down
+pub fn resume_jobs(http: Arc<serenity::Http>, data: &crate::Data) {
+    let pending = data.journal.pending();
+    if !pending.is_empty() {
+        tracing::info!("Resuming {} jobs from before the restart", pending.len());
+    }
+    for entry in pending {
+        let http = http.clone();
+        let queue = data.queue.clone();
+        let journal = data.journal.clone();
+        tokio::spawn(async move {
+            let (channel, message, job_id) = (entry.channel, entry.message, entry.job.id);
+            if let Err(err) = resume_job(&http, &queue, &journal, entry).await {
+                tracing::info!("Resumed job {} failed", *job_id);
+                err.log();
+                let edit = serenity::EditMessage::new()
+                    .content(format!("Error: {}", err))
+                    .components(vec![]);
+                // the message might be gone, nothing we can do about that
+                let _ = channel.edit_message(&http, message, edit).await;
+            }
+        });
+    }
+}
+
+/// run a job from the journal, telling its requester through the message we left them
+async fn resume_job(
+    http: &serenity::Http,
+    queue: &JobQueue,
+    journal: &Journal,
+    entry: JournalEntry,
+) -> crate::Result {
+    let job_id = entry.job.id;
+    let status = Status::Message(http, entry.channel, entry.message);
+    let result = async {
+        let ticket = queue.ticket(job_id, Some(entry.requester.into()), entry.priority)?;
+        status
+            .edit(CreateReply::default().content("Artifice restarted, getting back in line..."))
+            .await?;
+        let result = run_found_job(&status, queue, entry.job, entry.media, &ticket).await;
+        if ticket.control.is_cancelled() && !queue.is_closed() {
+            status
+                .edit(CreateReply::default().content("Cancelled.").components(vec![]))
+                .await?;
+            return Ok(());
+        }
+        result
+    }
+    .await;
+    if cut_off_by_shutdown(queue, &result) {
+        // shut down again before we got to it, it stays in the journal for next time
+        status
+            .edit(CreateReply::default().content(RESTARTING_MESSAGE).components(vec![]))
+            .await?;
+        return Ok(());
+    }
+    journal.remove(job_id).await?;
+    result
+}
+
 /// Add a caption to media.
 #[poise::command(slash_command)]
 pub async fn caption(
     ctx: Context<'_>,
     #[description = "Text to add"] caption: String,
     #[description = "Do you want the caption on the bottom?"] bottom: Option<bool>,
+    #[description = "Link to the media, instead of the last thing posted"] url: Option<MediaUrl>,
+    #[description = "Custom emoji to use as the media"] emoji: Option<CustomEmoji>,
+    #[description = "Use someone's avatar as the media"] user: Option<serenity::User>,
+    #[description = "Use this server's icon or banner as the media"] server: Option<ServerImage>,
 ) -> Result {
     handle_job(
         ctx,
@@ -96,6 +414,7 @@ pub async fn caption(
             },
             JobId(ctx.id()),
         ),
+        MediaTarget::from_args(url, emoji, user.as_ref(), server),
     )
     .await
 }
@@ -107,7 +426,11 @@ pub async fn caption(
 )]
 pub async fn bottom_caption(
     ctx: Context<'_>,
-    #[description = "Text to add"] caption: String
+    #[description = "Text to add"] caption: String,
+    #[description = "Link to the media, instead of the last thing posted"] url: Option<MediaUrl>,
+    #[description = "Custom emoji to use as the media"] emoji: Option<CustomEmoji>,
+    #[description = "Use someone's avatar as the media"] user: Option<serenity::User>,
+    #[description = "Use this server's icon or banner as the media"] server: Option<ServerImage>,
 ) -> Result {
     handle_job(
         ctx,
@@ -118,6 +441,79 @@ pub async fn bottom_caption(
             },
             JobId(ctx.id()),
         ),
+        MediaTarget::from_args(url, emoji, user.as_ref(), server),
+    )
+    .await
+}
+
+/// what the "Caption this" menu asks for
+#[derive(Debug, poise::Modal)]
+#[name = "Caption this"]
+struct CaptionModal {
+    #[name = "Caption"]
+    #[placeholder = "Text to add"]
+    #[paragraph]
+    // the macro only takes a number, this has to match `MAX_CAPTION_LEN`
+    #[max_length = 500]
+    text: String,
+}
+
+/// Caption the media on this message.
+#[poise::command(context_menu_command = "Caption this")]
+pub async fn caption_this(
+    ctx: crate::ApplicationContext<'_>,
+    #[description = "Message to caption"] message: serenity::Message,
+) -> Result {
+    use poise::Modal as _;
+    let Some(modal) = CaptionModal::execute(ctx).await? else {
+        // they closed it, or took too long
+        return Ok(());
+    };
+    let ctx = poise::Context::Application(ctx);
+    handle_job(
+        ctx,
+        Job::new_simple(
+            JobType::Caption {
+                text: modal.text,
+                bottom: false,
+            },
+            JobId(ctx.id()),
+        ),
+        Some(MediaTarget::Message(&message)),
     )
     .await
-}
\ No newline at end of file
+}
+
+/// Caption this user's avatar.
+#[poise::command(context_menu_command = "Caption avatar")]
+pub async fn caption_avatar(
+    ctx: crate::ApplicationContext<'_>,
+    #[description = "Whose avatar"] user: serenity::User,
+) -> Result {
+    use poise::Modal as _;
+    let Some(modal) = CaptionModal::execute(ctx).await? else {
+        return Ok(());
+    };
+    let ctx = poise::Context::Application(ctx);
+    handle_job(
+        ctx,
+        Job::new_simple(
+            JobType::Caption {
+                text: modal.text,
+                bottom: false,
+            },
+            JobId(ctx.id()),
+        ),
+        Some(MediaTarget::User(&user)),
+    )
+    .await
+}
+
+#[test]
+fn caption_modal_test() {
+    use poise::Modal as _;
+    // discord should stop them at the same length the caption would be turned away at
+    let modal = serde_json::to_value(CaptionModal::create(None, String::new())).unwrap();
+    let text = &modal["data"]["components"][0]["components"][0];
+    assert_eq!(text["max_length"], crate::captions::MAX_CAPTION_LEN);
+}
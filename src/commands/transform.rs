@@ -1,4 +1,7 @@
+use poise::serenity_prelude as serenity;
+
 use super::handle_job;
+use crate::media_helpers::{CustomEmoji, MediaTarget, MediaUrl, ServerImage};
 use crate::{Context, Job, JobId, JobType, Result};
 
 /// Rotate media.
@@ -6,10 +9,15 @@ use crate::{Context, Job, JobId, JobType, Result};
 pub async fn rotate(
     ctx: Context<'_>,
     #[description = "What angle?"] choice: crate::media_helpers::Rotation,
+    #[description = "Link to the media, instead of the last thing posted"] url: Option<MediaUrl>,
+    #[description = "Custom emoji to use as the media"] emoji: Option<CustomEmoji>,
+    #[description = "Use someone's avatar as the media"] user: Option<serenity::User>,
+    #[description = "Use this server's icon or banner as the media"] server: Option<ServerImage>,
 ) -> Result {
     handle_job(
         ctx,
         Job::new_simple(JobType::Rotate { rotation: choice }, JobId(ctx.id())),
+        MediaTarget::from_args(url, emoji, user.as_ref(), server),
     )
     .await
 }
@@ -26,6 +34,10 @@ pub async fn resize(
     #[min = 10]
     #[max = 8000]
     width: Option<u16>,
+    #[description = "Link to the media, instead of the last thing posted"] url: Option<MediaUrl>,
+    #[description = "Custom emoji to use as the media"] emoji: Option<CustomEmoji>,
+    #[description = "Use someone's avatar as the media"] user: Option<serenity::User>,
+    #[description = "Use this server's icon or banner as the media"] server: Option<ServerImage>,
 ) -> Result {
     handle_job(
         ctx,
@@ -36,6 +48,7 @@ pub async fn resize(
             },
             JobId(ctx.id()),
         ),
+        MediaTarget::from_args(url, emoji, user.as_ref(), server),
     )
     .await
 }
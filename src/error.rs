@@ -0,0 +1,104 @@
+// what can go wrong, and what we tell people about it.
+
+use std::time::Duration;
+
+/// everything that can go wrong while handling a job.
+/// `Display` is the friendly version that goes to discord, `details` is the whole story for the logs.
+#[derive(Debug)]
+pub enum ArtificeError {
+    /// asked for something that doesn't make sense, like a typo in a chain or nothing to work on
+    Invalid(String),
+    /// the media isn't something we can work with
+    Unsupported(String),
+    /// the media, or what was asked of it, is bigger than we allow
+    TooLarge(String),
+    /// couldn't get the media from wherever it lives
+    DownloadFailed(String),
+    /// ffmpeg gave up, with whatever it had to say about it
+    FfmpegFailed(String),
+    /// took longer than it was allowed to, so we killed it
+    TimedOut(Duration),
+    /// someone asked us to stop
+    Cancelled,
+    /// we're shutting down
+    Restarting,
+    /// our fault, and nothing the user can do about it. only shows up in the logs
+    Internal(String),
+}
+
+impl ArtificeError {
+    /// what to put in the logs, with everything we know
+    pub fn details(&self) -> String {
+        match self {
+            ArtificeError::DownloadFailed(reason) => format!("Download failed: {}", reason),
+            ArtificeError::FfmpegFailed(errors) => format!("ffmpeg failed:\n{}", errors),
+            ArtificeError::Internal(reason) => format!("Internal error: {}", reason),
+            other => other.to_string(),
+        }
+    }
+
+    /// whether this is something we should look into, rather than someone's bad input
+    pub fn is_our_fault(&self) -> bool {
+        matches!(
+            self,
+            ArtificeError::FfmpegFailed(_) | ArtificeError::TimedOut(_) | ArtificeError::Internal(_)
+        )
+    }
+
+    /// log this at the right level for how worried we should be
+    pub fn log(&self) {
+        if self.is_our_fault() {
+            tracing::warn!("{}", self.details());
+        } else {
+            tracing::info!("{}", self.details());
+        }
+    }
+}
+
+impl std::fmt::Display for ArtificeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtificeError::Invalid(message)
+            | ArtificeError::Unsupported(message)
+            | ArtificeError::TooLarge(message) => write!(f, "{}", message),
+            ArtificeError::DownloadFailed(_) => write!(f, "Couldn't download that media."),
+            ArtificeError::FfmpegFailed(_) => {
+                write!(f, "Processing failed, ffmpeg didn't like that file.")
+            }
+            ArtificeError::TimedOut(timeout) => write!(
+                f,
+                "Processing took longer than {}s and was stopped.",
+                timeout.as_secs()
+            ),
+            ArtificeError::Cancelled => write!(f, "Job cancelled."),
+            ArtificeError::Restarting => {
+                write!(f, "Artifice is restarting, try again in a minute.")
+            }
+            ArtificeError::Internal(_) => write!(f, "Something went wrong on our end, sorry!"),
+        }
+    }
+}
+
+// anything a library throws at us is our problem until proven otherwise.
+// (this is also why `ArtificeError` doesn't implement `std::error::Error` itself.)
+impl<E: std::error::Error + Send + Sync + 'static> From<E> for ArtificeError {
+    fn from(err: E) -> Self {
+        ArtificeError::Internal(err.to_string())
+    }
+}
+
+#[test]
+fn error_message_test() {
+    let ffmpeg = ArtificeError::FfmpegFailed("[libx264] height not divisible by 2".into());
+    // the gory details stay out of discord
+    assert!(!ffmpeg.to_string().contains("libx264"));
+    assert!(ffmpeg.details().contains("libx264"));
+    assert!(ffmpeg.is_our_fault());
+
+    let invalid = ArtificeError::Invalid("No media found.".into());
+    assert_eq!(invalid.to_string(), "No media found.");
+    assert!(!invalid.is_our_fault());
+
+    let io: ArtificeError = std::io::Error::other("disk on fire").into();
+    assert!(matches!(io, ArtificeError::Internal(_)));
+}
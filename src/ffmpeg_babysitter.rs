@@ -1,12 +1,371 @@
 // Make sure ffmpeg isnt silently dying on us.
 
-pub fn ffbabysit(mut baby: ffmpeg_sidecar::child::FfmpegChild) -> crate::Result {
+use std::io::Read;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use ffmpeg_sidecar::child::FfmpegChild;
+use ffmpeg_sidecar::command::FfmpegCommand;
+use ffmpeg_sidecar::event::{FfmpegEvent, LogLevel};
+
+use crate::ArtificeError;
+
+/// how often we check in on the baby
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// how far along a whole job is, shared by every handle to it
+#[derive(Debug)]
+struct JobProgress {
+    /// how done each part is, from 0 to 1
+    parts: Mutex<Vec<f32>>,
+    /// the average of `parts`, for whoever's watching
+    overall: tokio::sync::watch::Sender<f32>,
+}
+
+impl Default for JobProgress {
+    fn default() -> Self {
+        JobProgress {
+            parts: Mutex::new(vec![0.0]),
+            overall: tokio::sync::watch::channel(0.0).0,
+        }
+    }
+}
+
+/// the way a running job is kept in check from the outside.
+/// cloning gives another handle to the same job.
+#[derive(Clone, Default, Debug)]
+pub struct JobControl {
+    cancelled: Arc<AtomicBool>,
+    /// when ffmpeg has to be done by, and how long that gave it
+    deadline: Option<(Instant, Duration)>,
+    progress: Arc<JobProgress>,
+    /// which part of the job this handle is working on
+    part: usize,
+    /// the steps of the part running right now: (finished before these, running now, total)
+    steps: Option<(usize, usize, usize)>,
+    /// how long the media being worked on plays for, to turn ffmpeg's timestamps into a fraction
+    duration: Option<Duration>,
+}
+
+impl JobControl {
+    /// the same job, but any ffmpeg run with the result gets killed after `timeout`
+    pub fn with_timeout(&self, timeout: Duration) -> JobControl {
+        let mut control = self.clone();
+        control.deadline = Some((Instant::now() + timeout, timeout));
+        control
+    }
+
+    /// get ready to hear about `count` parts
+    pub fn track_parts(&self, count: usize) {
+        if let Ok(mut parts) = self.progress.parts.lock() {
+            parts.resize(count.max(1), 0.0);
+        }
+    }
+
+    /// the same job, reporting progress for part number `part`
+    pub fn for_part(&self, part: usize) -> JobControl {
+        let mut control = self.clone();
+        control.part = part;
+        control
+    }
+
+    /// the same job, currently running `running` of the part's `total` steps after `finished` others
+    pub fn at_steps(&self, finished: usize, running: usize, total: usize) -> JobControl {
+        let mut control = self.clone();
+        control.steps = Some((finished, running, total));
+        control
+    }
+
+    /// the same job, working on media that plays for `duration`
+    pub fn with_duration(&self, duration: Option<Duration>) -> JobControl {
+        let mut control = self.clone();
+        control.duration = duration;
+        control
+    }
+
+    /// watch how far along the whole job is, from 0 to 1
+    pub fn progress(&self) -> tokio::sync::watch::Receiver<f32> {
+        self.progress.overall.subscribe()
+    }
+
+    /// the steps we're running are `fraction` of the way done
+    pub fn report(&self, fraction: f32) {
+        let Some((finished, running, total)) = self.steps else {
+            return;
+        };
+        let done =
+            (finished as f32 + fraction.clamp(0.0, 1.0) * running as f32) / total.max(1) as f32;
+        self.set_part(done);
+    }
+
+    /// this handle's part is completely done
+    pub fn finish_part(&self) {
+        self.set_part(1.0);
+    }
+
+    /// ffmpeg got to `time` into the media
+    fn report_time(&self, time: Duration) {
+        if let Some(duration) = self.duration.filter(|duration| !duration.is_zero()) {
+            self.report(time.as_secs_f32() / duration.as_secs_f32());
+        }
+    }
+
+    fn set_part(&self, done: f32) {
+        let Ok(mut parts) = self.progress.parts.lock() else {
+            return;
+        };
+        if parts.len() <= self.part {
+            parts.resize(self.part + 1, 0.0);
+        }
+        parts[self.part] = done;
+        let overall = parts.iter().sum::<f32>() / parts.len() as f32;
+        self.progress.overall.send_replace(overall);
+    }
+
+    /// how long we were given, if we've run out of time
+    fn timed_out(&self) -> Option<Duration> {
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => Some(timeout),
+            _ => None,
+        }
+    }
+
+    /// ask the job to stop, any running ffmpeg gets killed
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// bail out if the job has been cancelled
+    pub fn check(&self) -> crate::Result {
+        if self.is_cancelled() {
+            return Err(ArtificeError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// caps on what a single ffmpeg process gets to use, from the environment.
+/// `FFMPEG_CPU_SECONDS` limits cpu time, `FFMPEG_MEMORY_MB` limits address space.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceLimits {
+    pub cpu_seconds: Option<u64>,
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn from_env() -> Self {
+        let read = |name: &str| std::env::var(name).ok().and_then(|value| value.parse::<u64>().ok());
+        ResourceLimits {
+            cpu_seconds: read("FFMPEG_CPU_SECONDS"),
+            memory_bytes: read("FFMPEG_MEMORY_MB").map(|mb| mb.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    /// read once, they don't change while we're running
+    pub fn get() -> ResourceLimits {
+        static LIMITS: OnceLock<ResourceLimits> = OnceLock::new();
+        *LIMITS.get_or_init(ResourceLimits::from_env)
+    }
+}
+
+/// start `command` with the configured `ResourceLimits` applied to the child.
+/// the kernel stops it if it goes over, which `ffbabysit` reports as `ArtificeError::TooLarge`.
+pub fn spawn_limited(command: &mut FfmpegCommand) -> crate::Result<FfmpegChild> {
+    apply_limits(command.as_inner_mut());
+    Ok(command.spawn()?)
+}
+
+/// run `command` to the end under the same `ResourceLimits`, killing it after `timeout`.
+/// for quick helpers like ffprobe, that don't need a whole `JobControl`.
+pub fn run_limited(command: &mut Command, timeout: Duration) -> crate::Result<Output> {
+    apply_limits(command);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // drain the pipes on the side, so a chatty child can't fill them up and stall
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            tracing::warn!(
+                "{} ran past its {}s timeout, killed it",
+                command.get_program().to_string_lossy(),
+                timeout.as_secs()
+            );
+            return Err(ArtificeError::TimedOut(timeout));
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    };
+
+    let joined = |reader: std::thread::JoinHandle<Vec<u8>>| {
+        reader
+            .join()
+            .map_err(|_| ArtificeError::Internal("pipe reader thread panicked".into()))
+    };
+    let output = Output {
+        status,
+        stdout: joined(stdout)?,
+        stderr: joined(stderr)?,
+    };
+    let errors = String::from_utf8_lossy(&output.stderr);
+    if over_limits(ResourceLimits::get(), status, &errors) {
+        return Err(limits_exceeded());
+    }
+    Ok(output)
+}
+
+/// read everything out of `pipe` on another thread
+fn drain(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut out = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut out);
+        }
+        out
+    })
+}
+
+/// have the kernel hold the child of `command` to the configured `ResourceLimits`
+fn apply_limits(command: &mut Command) {
+    #[cfg(target_os = "linux")]
+    {
+        let limits = ResourceLimits::get();
+        if limits.cpu_seconds.is_some() || limits.memory_bytes.is_some() {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: only setrlimit runs between fork and exec, which is async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    let set_limit = |resource, value: u64| {
+                        let limit = libc::rlimit {
+                            rlim_cur: value as libc::rlim_t,
+                            rlim_max: value as libc::rlim_t,
+                        };
+                        if libc::setrlimit(resource, &limit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    };
+                    if let Some(seconds) = limits.cpu_seconds {
+                        set_limit(libc::RLIMIT_CPU, seconds)?;
+                    }
+                    if let Some(bytes) = limits.memory_bytes {
+                        set_limit(libc::RLIMIT_AS, bytes)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = command;
+}
+
+/// whether a child that exited with `status` was stopped for going over `limits`.
+/// `errors` is whatever it managed to say on the way out.
+fn over_limits(limits: ResourceLimits, status: ExitStatus, errors: &str) -> bool {
+    if status.success() {
+        return false;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // too much cpu time gets a SIGXCPU, and a SIGKILL if it keeps going anyway
+        if limits.cpu_seconds.is_some()
+            && matches!(status.signal(), Some(libc::SIGXCPU | libc::SIGKILL))
+        {
+            return true;
+        }
+        // running out of address space just makes allocations fail, so ffmpeg complains about that
+        if limits.memory_bytes.is_some() {
+            let errors = errors.to_lowercase();
+            if errors.contains("cannot allocate memory") || errors.contains("out of memory") {
+                return true;
+            }
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (limits, errors);
+    false
+}
+
+/// what going over the `ResourceLimits` looks like to the user
+fn limits_exceeded() -> ArtificeError {
+    ArtificeError::TooLarge("That needs more CPU time or memory than we can give it.".into())
+}
+
+/// watch `baby` until it finishes, killing it if the job gets cancelled or runs out of time.
+/// timeouts come back as `ArtificeError::TimedOut`, going over the `ResourceLimits` as `ArtificeError::TooLarge`,
+/// and anything else ffmpeg complains about as `ArtificeError::FfmpegFailed`.
+pub fn ffbabysit(mut baby: FfmpegChild, control: &JobControl) -> crate::Result {
     // now we shall sit and watch the output stream of the ffmpeg process to see if we get any errors.
 
-    // let the baby do its thing
-    let _result = baby.wait()?;
+    // listen on another thread, so we can keep an eye on the baby itself over here.
+    // this also stops its output pipe from filling up and stalling it.
+    let events = baby
+        .iter()
+        .map_err(|err| ArtificeError::Internal(err.to_string()))?;
+    let reporter = control.clone();
+    let listener = std::thread::spawn(move || {
+        let mut errors = Vec::new();
+        for event in events {
+            match event {
+                FfmpegEvent::Progress(progress) => {
+                    if let Some(time) = parse_timestamp(&progress.time) {
+                        reporter.report_time(time);
+                    }
+                }
+                FfmpegEvent::Error(error)
+                | FfmpegEvent::Log(LogLevel::Error | LogLevel::Fatal, error) => errors.push(error),
+                _ => {}
+            }
+        }
+        errors
+    });
+
+    // let the baby do its thing, unless we're told to stop it
+    let status = loop {
+        if control.is_cancelled() {
+            baby.kill()?;
+            baby.wait()?;
+            return control.check();
+        }
+        if let Some(timeout) = control.timed_out() {
+            baby.kill()?;
+            baby.wait()?;
+            tracing::warn!("ffmpeg ran past its {}s timeout, killed it", timeout.as_secs());
+            return Err(ArtificeError::TimedOut(timeout));
+        }
+        if let Some(status) = baby.as_inner_mut().try_wait()? {
+            break status;
+        }
+        std::thread::sleep(CHECK_INTERVAL);
+    };
+
     // now inquire, and just keep the errors.
-    let possible_errors: Vec<String> = baby.iter().unwrap().filter_errors().collect();
+    let possible_errors: Vec<String> = listener
+        .join()
+        .map_err(|_| ArtificeError::Internal("ffmpeg listener thread panicked".into()))?;
+
+    // the kernel stepped in, ffmpeg usually has something confused to say about that too
+    if over_limits(ResourceLimits::get(), status, &possible_errors.join("\n")) {
+        tracing::warn!("ffmpeg went over its resource limits ({})", status);
+        return Err(limits_exceeded());
+    }
 
     // did any errors happen?
     if !possible_errors.is_empty() {
@@ -17,8 +376,92 @@ pub fn ffbabysit(mut baby: ffmpeg_sidecar::child::FfmpegChild) -> crate::Result
             error_string.push_str(&error);
             error_string.push('\n');
         }
-        return Err(error_string.into());
+        return Err(ArtificeError::FfmpegFailed(error_string));
+    }
+    // killed without a word
+    if !status.success() {
+        return Err(ArtificeError::FfmpegFailed(format!(
+            "ffmpeg exited with {}",
+            status
+        )));
     }
     // otherwise, no errors happened! yay!
     Ok(())
 }
+
+/// turn ffmpeg's "01:02:03.45" into a duration
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for piece in timestamp.trim().split(':') {
+        seconds = seconds * 60.0 + piece.parse::<f64>().ok()?;
+    }
+    // ffmpeg says negative times before it gets going
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[test]
+fn progress_test() {
+    assert_eq!(
+        parse_timestamp("00:01:02.50"),
+        Some(Duration::from_millis(62_500))
+    );
+    assert_eq!(parse_timestamp("-577014:32:22.77"), None);
+    assert_eq!(parse_timestamp("N/A"), None);
+
+    let control = JobControl::default();
+    let progress = control.progress();
+    control.track_parts(2);
+    // first part is halfway through the second of its two steps
+    control
+        .for_part(0)
+        .at_steps(1, 1, 2)
+        .with_duration(Some(Duration::from_secs(10)))
+        .report_time(Duration::from_secs(5));
+    assert_eq!(*progress.borrow(), 0.375);
+    control.for_part(1).finish_part();
+    assert_eq!(*progress.borrow(), 0.875);
+}
+
+#[test]
+fn timeout_test() {
+    ffmpeg_sidecar::download::auto_download().unwrap();
+    // an endless test pattern, only the timeout is going to stop this
+    let mut command = FfmpegCommand::new();
+    command
+        .args(["-f", "lavfi", "-i", "testsrc=size=64x64:rate=10"])
+        .args(["-f", "null", "-"]);
+    let child = spawn_limited(&mut command).unwrap();
+    let control = JobControl::default().with_timeout(Duration::from_millis(500));
+    let error = ffbabysit(child, &control).unwrap_err();
+    assert!(matches!(error, ArtificeError::TimedOut(_)));
+}
+
+#[test]
+fn run_limited_test() {
+    let output = run_limited(Command::new("echo").arg("hi"), Duration::from_secs(5)).unwrap();
+    assert_eq!(output.stdout, b"hi\n");
+
+    let error =
+        run_limited(Command::new("sleep").arg("5"), Duration::from_millis(200)).unwrap_err();
+    assert!(matches!(error, ArtificeError::TimedOut(_)));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn over_limits_test() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let limited = ResourceLimits {
+        cpu_seconds: Some(10),
+        memory_bytes: Some(1024 * 1024 * 1024),
+    };
+    let xcpu = ExitStatus::from_raw(libc::SIGXCPU);
+    let failed = ExitStatus::from_raw(1 << 8);
+    let oom = "Error: Cannot allocate memory";
+    assert!(over_limits(limited, xcpu, ""));
+    assert!(over_limits(limited, failed, oom));
+    assert!(!over_limits(limited, failed, "Invalid data found"));
+    assert!(!over_limits(limited, ExitStatus::from_raw(0), oom));
+    // no limits, so it can't have gone over them
+    assert!(!over_limits(ResourceLimits::default(), xcpu, ""));
+}
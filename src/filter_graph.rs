@@ -0,0 +1,213 @@
+// a tiny builder for ffmpeg filter graphs, so nobody has to hand-write `-filter_complex` strings.
+//
+// ffmpeg parses a filter graph twice: once to split it into filters, and once more
+// to split each filter's arguments. every value we render gets escaped for both,
+// so user text can't break out and add filters of its own.
+
+/// a single ffmpeg filter, ie `scale=256:-2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    name: &'static str,
+    /// (key, value), positional arguments have no key
+    args: Vec<(Option<&'static str>, String)>,
+}
+
+impl Filter {
+    pub fn new(name: &'static str) -> Self {
+        Filter {
+            name,
+            args: Vec::new(),
+        }
+    }
+
+    /// add a positional argument
+    pub fn arg(mut self, value: impl ToString) -> Self {
+        self.args.push((None, value.to_string()));
+        self
+    }
+
+    /// add a `key=value` argument
+    pub fn option(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.args.push((Some(key), value.to_string()));
+        self
+    }
+
+    fn render(&self) -> String {
+        let mut out = self.name.to_string();
+        for (i, (key, value)) in self.args.iter().enumerate() {
+            out.push(if i == 0 { '=' } else { ':' });
+            if let Some(key) = key {
+                out.push_str(key);
+                out.push('=');
+            }
+            out.push_str(&escape_value(value));
+        }
+        escape_graph(&out)
+    }
+}
+
+/// a labelled pad connecting filters together, ie `[0:v]` or `[stacked]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pad(String);
+
+impl Pad {
+    /// the first video stream of input number `index`
+    pub fn video_input(index: usize) -> Self {
+        Pad(format!("{}:v", index))
+    }
+
+    /// the first audio stream of input number `index`
+    pub fn audio_input(index: usize) -> Self {
+        Pad(format!("{}:a", index))
+    }
+
+    /// the label, for use with `-map`
+    pub fn label(&self) -> String {
+        format!("[{}]", self.0)
+    }
+}
+
+/// filters run one after another, between some input and output pads
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FilterChain {
+    inputs: Vec<Pad>,
+    filters: Vec<Filter>,
+    /// no outputs means ffmpeg picks the result up for the output file on its own
+    outputs: Vec<Pad>,
+}
+
+/// a whole `-filter_complex` graph
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterGraph {
+    chains: Vec<FilterChain>,
+    next_label: usize,
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a graph that runs `filters` on the first video stream of the first input
+    pub fn simple(filters: Vec<Filter>) -> Self {
+        let mut graph = Self::new();
+        graph.chain(vec![Pad::video_input(0)], filters, vec![]);
+        graph
+    }
+
+    /// get a fresh pad to connect two chains with
+    pub fn pad(&mut self) -> Pad {
+        self.next_label += 1;
+        Pad(format!("p{}", self.next_label))
+    }
+
+    /// add a chain of filters
+    pub fn chain(&mut self, inputs: Vec<Pad>, filters: Vec<Filter>, outputs: Vec<Pad>) {
+        self.chains.push(FilterChain {
+            inputs,
+            filters,
+            outputs,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chains.iter().all(|chain| chain.filters.is_empty())
+    }
+
+    /// the graph as ffmpeg wants it
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (i, chain) in self.chains.iter().filter(|c| !c.filters.is_empty()).enumerate() {
+            if i != 0 {
+                out.push(';');
+            }
+            for pad in &chain.inputs {
+                out.push_str(&pad.label());
+            }
+            let filters: Vec<String> = chain.filters.iter().map(Filter::render).collect();
+            out.push_str(&filters.join(","));
+            for pad in &chain.outputs {
+                out.push_str(&pad.label());
+            }
+        }
+        out
+    }
+
+    /// arguments to hand to `FfmpegCommand::args`
+    pub fn to_args(&self) -> [String; 2] {
+        ["-filter_complex".to_string(), self.render()]
+    }
+}
+
+/// quote a filter argument so `:` `=` and friends are taken literally
+fn escape_value(value: &str) -> String {
+    // nothing to worry about? don't make the command harder to read
+    if value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-+.*/_()".contains(c))
+        && !value.is_empty()
+    {
+        return value.to_string();
+    }
+    // inside quotes everything is literal except the quote itself,
+    // which has to close the quotes, get escaped, and reopen them.
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('\'');
+    for c in value.chars() {
+        if c == '\'' {
+            out.push_str("'\\''");
+        } else {
+            out.push(c);
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// escape the characters the graph parser cares about
+fn escape_graph(filter: &str) -> String {
+    let mut out = String::with_capacity(filter.len());
+    for c in filter.chars() {
+        if "\\'[],;".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[test]
+fn filter_graph_render_test() {
+    // the basics
+    let graph = FilterGraph::simple(vec![
+        Filter::new("scale").arg(256).arg(-2),
+        Filter::new("hflip"),
+    ]);
+    assert_eq!(graph.render(), "[0:v]scale=256:-2,hflip");
+
+    // two inputs, stacked, through a labelled pad
+    let mut graph = FilterGraph::new();
+    let stacked = graph.pad();
+    graph.chain(
+        vec![Pad::video_input(1), Pad::video_input(0)],
+        vec![Filter::new("vstack").option("inputs", 2)],
+        vec![stacked.clone()],
+    );
+    graph.chain(vec![stacked], vec![Filter::new("vflip")], vec![]);
+    assert_eq!(graph.render(), "[1:v][0:v]vstack=inputs=2[p1];[p1]vflip");
+
+    // empty graphs stay empty
+    assert!(FilterGraph::simple(vec![]).is_empty());
+}
+
+#[test]
+fn filter_graph_escape_test() {
+    // user text trying to sneak in another filter
+    let filter = Filter::new("drawtext").option("text", "it's a [trap]; scale=1:1, hi");
+    assert_eq!(
+        filter.render(),
+        r"drawtext=text=\'it\'\\\'\'s a \[trap\]\; scale=1:1\, hi\'"
+    );
+    // plain values are left alone
+    assert_eq!(Filter::new("transpose").arg(1).render(), "transpose=1");
+}
@@ -0,0 +1,355 @@
+// local http api, for when discord isn't the one asking.
+//
+// POST /jobs             multipart with a `file` and an `operations` json list,
+//                        ie `[{"op": "resize", "height": 256}, {"op": "rotate", "rotation": "90"}]`.
+//                        add `?wait=true` to get the finished file back directly,
+//                        otherwise you get a job id to poll.
+// GET  /jobs/:id         where the job is at.
+// GET  /jobs/:id/result  the finished file. it's only kept until you grab it.
+//
+// jobs wait in the same queue and share the same permits as the discord side.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::multipart::Field;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Multipart, Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::ffmpeg_babysitter::JobControl;
+use crate::job::{Job, JobId, JobPart, JobType};
+use crate::media_helpers::{new_temp_media, Media};
+use crate::pipeline::{run_job, validate_part};
+use crate::queue::{JobQueue, JobTicket, Priority, Requester};
+use crate::sniff::{identify, sniff, SNIFF_LEN};
+use crate::ArtificeError;
+
+/// biggest upload we'll take
+const MAX_UPLOAD_BYTES: usize = 100 * 1024 * 1024;
+/// how long finished results hang around if nobody picks them up
+const RESULT_TTL: Duration = Duration::from_secs(10 * 60);
+
+type ApiError = (StatusCode, String);
+
+enum JobStatus {
+    Queued,
+    Processing,
+    Done(Media),
+    Failed(String),
+}
+
+#[derive(Clone)]
+struct ApiState {
+    queue: JobQueue,
+    jobs: Arc<Mutex<HashMap<JobId, JobStatus>>>,
+    /// if set, requests need `Authorization: Bearer <token>`
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SubmitQuery {
+    #[serde(default)]
+    wait: bool,
+}
+
+/// run the api until something goes horribly wrong.
+/// listens on `$HTTP_BIND`, or localhost:8080 if that isn't set.
+pub async fn serve(queue: JobQueue) -> crate::Result {
+    let bind = std::env::var("HTTP_BIND").unwrap_or("127.0.0.1:8080".to_string());
+    let state = ApiState {
+        queue,
+        jobs: Arc::default(),
+        token: std::env::var("HTTP_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty()),
+    };
+    let app = Router::new()
+        .route("/jobs", post(submit))
+        .route("/jobs/:id", get(status))
+        .route("/jobs/:id/result", get(result))
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&bind).await?;
+    info!("HTTP API listening on {}", bind);
+    // clients are told apart by address, for taking turns in the queue
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn submit(
+    State(state): State<ApiState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<SubmitQuery>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, ApiError> {
+    check_token(&state, &headers)?;
+
+    let mut upload: Option<Media> = None;
+    let mut operations: Option<Vec<JobType>> = None;
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "file" => upload = Some(save_upload(field).await?),
+            "operations" => {
+                let text = field.text().await.map_err(bad_request)?;
+                operations = Some(serde_json::from_str(&text).map_err(bad_request)?);
+            }
+            _ => {}
+        }
+    }
+    let media = upload.ok_or_else(|| bad_request("Missing `file`."))?;
+    let operations = operations.ok_or_else(|| bad_request("Missing `operations`."))?;
+
+    let part = JobPart::from_operations(operations).map_err(bad_request)?;
+    validate_part(&part, media.media_type).map_err(bad_request)?;
+
+    let id = JobId(rand::random());
+    // before saying yes, so clients over the cap find out right away
+    let ticket = state
+        .queue
+        .ticket(id, Some(Requester::Api(peer.ip())), Priority::Normal)
+        .map_err(refused)?;
+    state.jobs.lock().await.insert(id, JobStatus::Queued);
+    let job = Job::new_chain(part, id);
+
+    if query.wait {
+        // if the client hangs up, this gets dropped, which stops the job and forgets about it
+        process(state.clone(), job, media, ticket).await;
+        return take_result(&state, id).await;
+    }
+
+    tokio::spawn(process(state, job, media, ticket));
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "id": id.0.to_string() })),
+    )
+        .into_response())
+}
+
+async fn status(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    check_token(&state, &headers)?;
+    let id = JobId(id);
+    let summary = match state.jobs.lock().await.get(&id).ok_or_else(not_found)? {
+        JobStatus::Queued => None,
+        JobStatus::Processing => Some(json!({ "status": "processing" })),
+        JobStatus::Done(_) => Some(json!({ "status": "done" })),
+        JobStatus::Failed(error) => Some(json!({ "status": "failed", "error": error })),
+    };
+    // the jobs lock is let go by now, so asking the queue doesn't hold up everyone else
+    Ok(Json(match summary {
+        Some(summary) => summary,
+        None => json!({
+            "status": "queued",
+            "position": state.queue.position(id).await,
+            "eta_secs": state.queue.eta(id).await.map(|eta| eta.as_secs()),
+        }),
+    }))
+}
+
+async fn result(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<Response, ApiError> {
+    check_token(&state, &headers)?;
+    take_result(&state, JobId(id)).await
+}
+
+/// stops a job that nobody's waiting on anymore, for when a `?wait=true` client hangs up.
+/// dropping `process` early drops the job's tasks, but not the ffmpeg they started.
+struct Abandoned {
+    id: JobId,
+    jobs: Arc<Mutex<HashMap<JobId, JobStatus>>>,
+    control: JobControl,
+    finished: bool,
+}
+
+impl Drop for Abandoned {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.control.cancel();
+        let (jobs, id) = (self.jobs.clone(), self.id);
+        tokio::spawn(async move {
+            jobs.lock().await.remove(&id);
+        });
+    }
+}
+
+/// wait in line, run the job, and leave the outcome in `state.jobs`
+async fn process(state: ApiState, job: Job, media: Media, ticket: JobTicket) {
+    let id = job.id;
+    let timings = state.queue.timings();
+    let mut abandoned = Abandoned {
+        id,
+        jobs: state.jobs.clone(),
+        control: ticket.control.clone(),
+        finished: false,
+    };
+    let outcome = async {
+        if let Some(part) = job.parts.first() {
+            let size = std::fs::metadata(&media.file_path.path)
+                .ok()
+                .map(|m| m.len());
+            let estimate = timings.estimate(part, media.media_type, size);
+            state.queue.set_estimate(&ticket, estimate);
+        }
+        let turn = state
+            .queue
+            .wait_with(&ticket, |_, _| async { Ok::<(), crate::Error>(()) })
+            .await?;
+        state.jobs.lock().await.insert(id, JobStatus::Processing);
+        let mut results = run_job(job, vec![media], turn, ticket.control.clone(), timings).await?;
+        results.pop().ok_or_else(|| {
+            ArtificeError::Internal("Operation did not produce an output file.".into())
+        })
+    }
+    .await;
+
+    let status = match outcome {
+        Ok(media) => JobStatus::Done(media),
+        Err(err) => {
+            err.log();
+            JobStatus::Failed(err.to_string())
+        }
+    };
+    state.jobs.lock().await.insert(id, status);
+    abandoned.finished = true;
+
+    // don't hold on to results forever
+    let jobs = state.jobs.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(RESULT_TTL).await;
+        jobs.lock().await.remove(&id);
+    });
+}
+
+/// hand over a finished file, forgetting about the job once it's taken
+async fn take_result(state: &ApiState, id: JobId) -> Result<Response, ApiError> {
+    let media = {
+        let mut jobs = state.jobs.lock().await;
+        match jobs.remove(&id).ok_or_else(not_found)? {
+            JobStatus::Done(media) => media,
+            JobStatus::Failed(error) => {
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, error));
+            }
+            unfinished => {
+                // not ready yet, put it back
+                jobs.insert(id, unfinished);
+                return Err((StatusCode::CONFLICT, "Job is not finished yet.".to_string()));
+            }
+        }
+    };
+    let output = media
+        .output_tempfile
+        .ok_or_else(|| internal("Operation did not produce an output file."))?;
+    let data = tokio::fs::read(&output.path).await.map_err(internal)?;
+    let file_name = output
+        .path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or("output")
+        .to_string();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+/// write an uploaded file into a temp folder
+async fn save_upload(field: Field<'_>) -> Result<Media, ApiError> {
+    let file_name = field.file_name().unwrap_or_default().to_string();
+    let extension = std::path::Path::new(&file_name)
+        .extension()
+        .and_then(OsStr::to_str)
+        .filter(|extension| extension.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(str::to_string);
+
+    let data = field.bytes().await.map_err(bad_request)?;
+    // no extension is fine, as long as the file itself tells us what it is
+    let extension = extension
+        .or_else(|| sniff(&data[..data.len().min(SNIFF_LEN)]).map(|s| s.extension.to_string()))
+        .ok_or_else(|| bad_request("Unknown media type."))?;
+    let holder = new_temp_media(OsStr::new(&extension));
+    tokio::fs::write(&holder.path, &data)
+        .await
+        .map_err(internal)?;
+
+    // whatever the client claimed, go by what's actually in there
+    let path = holder.path.clone();
+    let media_type = tokio::task::spawn_blocking(move || identify(&path))
+        .await
+        .map_err(internal)?
+        .map_err(bad_request)?;
+
+    Ok(Media {
+        media_type,
+        file_path: holder,
+        output_tempfile: None,
+    })
+}
+
+fn check_token(state: &ApiState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = &state.token else {
+        return Ok(());
+    };
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if given == Some(token.as_str()) {
+        Ok(())
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "Bad token.".to_string()))
+    }
+}
+
+fn bad_request(err: impl ToString) -> ApiError {
+    (StatusCode::BAD_REQUEST, err.to_string())
+}
+
+/// the queue wouldn't take the job
+fn refused(err: ArtificeError) -> ApiError {
+    let status = match err {
+        ArtificeError::Invalid(_) => StatusCode::TOO_MANY_REQUESTS,
+        ArtificeError::Restarting => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string())
+}
+
+fn not_found() -> ApiError {
+    (StatusCode::NOT_FOUND, "No such job.".to_string())
+}
+
+fn internal(err: impl ToString) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
@@ -1,8 +1,14 @@
-#[derive(Clone, Copy, Debug, bevy_derive::Deref, PartialEq, Eq)]
+use poise::ChoiceParameter;
+use serde::{Deserialize, Serialize};
+
+use crate::media_helpers::Rotation;
+use crate::operations::{self, MediaOperation};
+
+#[derive(Clone, Copy, Debug, bevy_derive::Deref, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct JobId(pub u64);
 
 /// a bunch of work that we need to do to respond to a given interaction
-#[derive(Debug, Clone, Eq)]
+#[derive(Debug, Clone, Eq, Serialize, Deserialize)]
 pub struct Job {
     /// id that initiated this job; used for equality
     pub id: JobId,
@@ -12,15 +18,30 @@ pub struct Job {
 
 impl Job {
     pub fn new_simple(ty: JobType, /* url: Arc<str>, */ id: JobId) -> Job {
-        Self {
-            parts: [JobPart {
+        Self::new_chain(
+            JobPart {
                 subparts: [ty].into(),
                 // download_url: url,
-            }]
-            .into(),
+            },
+            id,
+        )
+    }
+
+    /// a job that runs every subpart of `part` in order on the same media
+    pub fn new_chain(part: JobPart, id: JobId) -> Job {
+        Self {
+            parts: [part].into(),
             id,
         }
     }
+
+    /// make sure there is one part for each of `count` media files.
+    /// commands only describe the first part, so the extra ones copy it.
+    pub fn spread_over(&mut self, count: usize) {
+        if let Some(template) = self.parts.first().cloned() {
+            self.parts.resize(count.max(1), template);
+        }
+    }
 }
 
 impl PartialEq for Job {
@@ -29,7 +50,7 @@ impl PartialEq for Job {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct JobPart {
     /// if you chain multiple actions each gets an entry here
     pub subparts: smallvec::SmallVec<[JobType; 1]>,
@@ -37,17 +58,259 @@ pub struct JobPart {
     // pub download_url: Arc<str>, // bad idea! -Doc
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// the most operations we are willing to stack in a single chain
+pub const MAX_CHAIN_LENGTH: usize = 8;
+
+// same limits as the /resize command
+const MIN_SIZE: u16 = 10;
+pub const MAX_SIZE: u16 = 8000;
+
+impl std::str::FromStr for JobPart {
+    type Err = String;
+
+    /// parse a chain like `resize 256 | rotate 90 | caption "hi"`
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let steps = split_chain(input)?;
+        let subparts = steps
+            .iter()
+            .map(|words| JobType::from_words(words))
+            .collect::<Result<Vec<_>, _>>()?;
+        JobPart::from_operations(subparts)
+    }
+}
+
+impl JobPart {
+    /// build a part out of already-parsed operations, checking they're all within limits
+    pub fn from_operations(operations: Vec<JobType>) -> Result<Self, String> {
+        if operations.is_empty() {
+            return Err("Nothing to do.".to_string());
+        }
+        if operations.len() > MAX_CHAIN_LENGTH {
+            return Err(format!(
+                "Chains can have at most {} operations.",
+                MAX_CHAIN_LENGTH
+            ));
+        }
+        for operation in &operations {
+            operation.check_limits()?;
+        }
+        Ok(JobPart {
+            subparts: operations.into(),
+        })
+    }
+}
+
+/// split a chain into steps of words.
+/// anything inside quotes stays together, `|` inside quotes is not a separator.
+fn split_chain(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut steps: Vec<Vec<String>> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    // set when a quote was opened, so `caption ""` still gives us a word
+    let mut word_started = false;
+    let mut quoted = false;
+
+    for c in input.chars() {
+        match c {
+            // phones love to send fancy quotes
+            '"' | '“' | '”' => {
+                quoted = !quoted;
+                word_started = true;
+            }
+            '|' if !quoted => {
+                if word_started || !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                word_started = false;
+                steps.push(std::mem::take(&mut words));
+            }
+            c if c.is_whitespace() && !quoted => {
+                if word_started || !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                word_started = false;
+            }
+            c => word.push(c),
+        }
+    }
+
+    if quoted {
+        return Err("Unclosed quote in chain.".to_string());
+    }
+    if word_started || !word.is_empty() {
+        words.push(word);
+    }
+    steps.push(words);
+
+    if steps.iter().any(Vec::is_empty) {
+        return Err("Empty step in chain.".to_string());
+    }
+    Ok(steps)
+}
+
+/// serialized as `{"op": "resize", "height": 256}` and friends
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
 pub enum JobType {
     Resize {
+        #[serde(default)]
         width: u16,
         height: u16,
     },
     Caption {
         text: String,
+        #[serde(default)]
         bottom: bool
     },
     Rotate {
         rotation: crate::media_helpers::Rotation,
     }, // #TODO
 }
+
+impl JobType {
+    /// the operation that does the actual work for this job
+    pub fn operation(&self) -> Box<dyn MediaOperation> {
+        match self.clone() {
+            JobType::Resize { width, height } => Box::new(operations::Resize { width, height }),
+            JobType::Caption { text, bottom } => Box::new(operations::Caption { text, bottom }),
+            JobType::Rotate { rotation } => Box::new(operations::Rotate { rotation }),
+        }
+    }
+
+    /// make sure the numbers are sane, since not everything comes in through discord's limits
+    pub fn check_limits(&self) -> Result<(), String> {
+        match self {
+            JobType::Resize { width, height } => {
+                check_size(*height)?;
+                // 0 means keep the aspect ratio
+                if *width != 0 {
+                    check_size(*width)?;
+                }
+                Ok(())
+            }
+            JobType::Caption { .. } | JobType::Rotate { .. } => Ok(()),
+        }
+    }
+
+    /// build a single operation out of a chain step, ie `["resize", "256"]`
+    fn from_words(words: &[String]) -> Result<JobType, String> {
+        let (name, args) = words.split_first().ok_or("Empty step in chain.")?;
+        match name.to_lowercase().as_str() {
+            "resize" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err("Usage: resize <height> [width]".to_string());
+                }
+                let height = parse_size(&args[0])?;
+                let width = match args.get(1) {
+                    Some(width) => parse_size(width)?,
+                    // 0 means keep the aspect ratio
+                    None => 0,
+                };
+                Ok(JobType::Resize { width, height })
+            }
+            "rotate" => {
+                let [choice] = args else {
+                    return Err("Usage: rotate <90|90ccw|180|vflip|hflip>".to_string());
+                };
+                let rotation = Rotation::from_name(choice)
+                    .ok_or_else(|| format!("Unknown rotation `{}`.", choice))?;
+                Ok(JobType::Rotate { rotation })
+            }
+            "caption" | "bottomcaption" => {
+                let text = args.join(" ");
+                if text.is_empty() {
+                    return Err(format!("Usage: {} \"text\"", name));
+                }
+                Ok(JobType::Caption {
+                    text,
+                    bottom: name.eq_ignore_ascii_case("bottomcaption"),
+                })
+            }
+            _ => Err(format!("Unknown operation `{}`.", name)),
+        }
+    }
+}
+
+fn parse_size(number: &str) -> Result<u16, String> {
+    number
+        .parse::<u16>()
+        .map_err(|_| format!("`{}` is not a size.", number))
+}
+
+fn check_size(size: u16) -> Result<(), String> {
+    if (MIN_SIZE..=MAX_SIZE).contains(&size) {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` is not a size between {} and {}.",
+            size, MIN_SIZE, MAX_SIZE
+        ))
+    }
+}
+
+#[test]
+fn chain_parse_test() {
+    let part: JobPart = r#"resize 256 | rotate 90 | caption "hi | there""#
+        .parse()
+        .unwrap();
+    assert_eq!(
+        part.subparts.as_slice(),
+        [
+            JobType::Resize {
+                width: 0,
+                height: 256
+            },
+            JobType::Rotate {
+                rotation: Rotation::Cw
+            },
+            JobType::Caption {
+                text: "hi | there".to_string(),
+                bottom: false
+            },
+        ]
+    );
+
+    let part: JobPart = "RESIZE 100 200|bottomcaption “phone quotes”".parse().unwrap();
+    assert_eq!(
+        part.subparts.as_slice(),
+        [
+            JobType::Resize {
+                width: 200,
+                height: 100
+            },
+            JobType::Caption {
+                text: "phone quotes".to_string(),
+                bottom: true
+            },
+        ]
+    );
+
+    // things that should be rejected
+    for bad in [
+        "",
+        "resize 256 |",
+        "resize 5",
+        "resize",
+        "rotate 45",
+        "caption \"unclosed",
+        "caption",
+        "explode",
+        "rotate 90 | rotate 90 | rotate 90 | rotate 90 | rotate 90 | rotate 90 | rotate 90 | rotate 90 | rotate 90",
+    ] {
+        assert!(bad.parse::<JobPart>().is_err(), "{bad:?} should not parse");
+    }
+}
+
+#[test]
+fn job_type_json_test() {
+    let operations: Vec<JobType> = serde_json::from_str(
+        r#"[{"op": "resize", "height": 256}, {"op": "rotate", "rotation": "90ccw"}, {"op": "caption", "text": "hi"}]"#,
+    )
+    .unwrap();
+    let part = JobPart::from_operations(operations).unwrap();
+    assert_eq!(part, "resize 256 | rotate 90ccw | caption hi".parse().unwrap());
+
+    // limits still apply
+    let too_big: Vec<JobType> = serde_json::from_str(r#"[{"op": "resize", "height": 9000}]"#).unwrap();
+    assert!(JobPart::from_operations(too_big).is_err());
+}
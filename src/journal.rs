@@ -0,0 +1,149 @@
+// remembering who was waiting, so a restart doesn't forget about them.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use poise::serenity_prelude::{ChannelId, MessageId, UserId};
+use serde::{Deserialize, Serialize};
+
+use crate::job::{Job, JobId};
+use crate::media_helpers::UrlAndMediaType;
+use crate::queue::Priority;
+use crate::ArtificeError;
+
+/// where the journal lives if `JOURNAL_PATH` isn't set
+pub const DEFAULT_JOURNAL_PATH: &str = "artifice-journal.json";
+
+/// everything needed to pick a job back up after a restart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub job: Job,
+    /// the media we already found for it, one per part
+    pub media: Vec<UrlAndMediaType>,
+    pub requester: UserId,
+    pub priority: Priority,
+    /// the message we keep editing with the job's status
+    pub channel: ChannelId,
+    pub message: MessageId,
+}
+
+/// discord jobs that haven't finished yet, mirrored to a file on every change
+pub struct Journal {
+    path: PathBuf,
+    /// in the order they showed up
+    entries: Mutex<Vec<JournalEntry>>,
+    /// held while the file is being written, so writes can't land out of order
+    writing: tokio::sync::Mutex<()>,
+}
+
+impl Journal {
+    /// load whatever was left in `path` last time.
+    /// a missing or broken journal just means starting with nothing.
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                tracing::warn!("Ignoring unreadable journal {}: {}", path.display(), err);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Journal {
+            path,
+            entries: Mutex::new(entries),
+            writing: Default::default(),
+        }
+    }
+
+    /// the jobs that were still going when we last stopped
+    pub fn pending(&self) -> Vec<JournalEntry> {
+        self.entries
+            .lock()
+            .map(|entries| entries.clone())
+            .unwrap_or_default()
+    }
+
+    /// remember `entry` until it gets removed
+    pub async fn record(&self, entry: JournalEntry) -> crate::Result {
+        {
+            let mut entries = self.entries.lock().map_err(|_| poisoned())?;
+            entries.retain(|other| other.job.id != entry.job.id);
+            entries.push(entry);
+        }
+        self.save().await
+    }
+
+    /// forget about `job`, it's done one way or another
+    pub async fn remove(&self, job: JobId) -> crate::Result {
+        {
+            let mut entries = self.entries.lock().map_err(|_| poisoned())?;
+            let before = entries.len();
+            entries.retain(|other| other.job.id != job);
+            if entries.len() == before {
+                return Ok(());
+            }
+        }
+        self.save().await
+    }
+
+    /// write out the entries as they are now, without blocking the async threads
+    async fn save(&self) -> crate::Result {
+        // one write at a time, each with whatever's newest by the time it gets a go
+        let _writing = self.writing.lock().await;
+        let bytes = serde_json::to_vec(&*self.entries.lock().map_err(|_| poisoned())?)?;
+        let (temp, path) = (self.path.with_extension("tmp"), self.path.clone());
+        tokio::task::spawn_blocking(move || {
+            // write then rename, so dying halfway through can't leave half a journal
+            std::fs::write(&temp, bytes)?;
+            std::fs::rename(&temp, &path)?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+fn poisoned() -> ArtificeError {
+    ArtificeError::Internal("Journal is poisoned.".into())
+}
+
+#[tokio::test]
+async fn journal_test() {
+    use crate::job::JobType;
+    use crate::media_helpers::MediaType;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.json");
+    let entry = |id: u64| JournalEntry {
+        job: Job::new_simple(
+            JobType::Caption {
+                text: "hi".into(),
+                bottom: false,
+            },
+            JobId(id),
+        ),
+        media: vec![UrlAndMediaType {
+            url: "https://example.com/cat.png".into(),
+            media_type: MediaType::Image,
+            size: Some(1234),
+        }],
+        requester: UserId::new(1),
+        priority: Priority::Normal,
+        channel: ChannelId::new(2),
+        message: MessageId::new(3),
+    };
+
+    let journal = Journal::open(&path);
+    assert!(journal.pending().is_empty());
+    journal.record(entry(1)).await.unwrap();
+    journal.record(entry(2)).await.unwrap();
+    journal.record(entry(3)).await.unwrap();
+    journal.remove(JobId(2)).await.unwrap();
+
+    // pretend we restarted
+    let reopened = Journal::open(&path);
+    let pending = reopened.pending();
+    let ids: Vec<u64> = pending.iter().map(|entry| entry.job.id.0).collect();
+    assert_eq!(ids, [1, 3]);
+    assert_eq!(pending[0].job.parts, entry(1).job.parts);
+    assert_eq!(pending[0].media[0].url, "https://example.com/cat.png");
+}
@@ -0,0 +1,41 @@
+// everything artifice can do, shared between the discord bot and the cli.
+
+pub mod commands;
+pub mod job;
+pub mod queue;
+
+pub use error::ArtificeError;
+pub use job::*;
+
+pub type Error = ArtificeError;
+pub type Result<T = ()> = std::result::Result<T, Error>;
+pub type Context<'a> = poise::Context<'a, Data, Error>;
+pub type ApplicationContext<'a> = poise::ApplicationContext<'a, Data, Error>;
+
+// Custom user data passed to all command functions
+pub struct Data {
+    pub queue: queue::JobQueue,
+    /// members with any of these roles get their jobs run first
+    pub priority_roles: std::collections::HashSet<poise::serenity_prelude::RoleId>,
+    /// discord jobs that should survive a restart
+    pub journal: std::sync::Arc<journal::Journal>,
+    /// recent media in each channel, so searches can skip asking discord
+    pub media_cache: media_cache::MediaCache,
+}
+
+pub mod captions;
+pub mod error;
+pub mod ffmpeg_babysitter;
+pub mod filter_graph;
+#[cfg(feature = "http")]
+pub mod http_api;
+pub mod journal;
+pub mod media_cache;
+pub mod media_helpers; // for linting reasons // ditto
+pub mod net;
+pub mod operations;
+pub mod pipeline;
+pub mod probe;
+pub mod resolvers;
+pub mod sniff;
+pub mod timings;
@@ -1,27 +1,15 @@
-mod commands;
-mod job;
-mod queue;
-
+use artifice::media_cache::MediaCache;
+use artifice::media_helpers::SearchLimits;
+use artifice::{commands, queue, Data, Error};
 use poise::serenity_prelude as serenity;
 use std::collections::HashSet;
+use std::time::Duration;
 
-pub use job::*;
-
-type Error = Box<dyn std::error::Error + Send + Sync>;
-type Result<T = ()> = std::result::Result<T, Error>;
-type Context<'a> = poise::Context<'a, Data, Error>;
-
-// Custom user data passed to all command functions
-pub struct Data {
-    pub queue: queue::JobQueue,
-    pub job_semaphore: tokio::sync::Semaphore,
-}
-
-// import the commands
+/// how many jobs we work on at once
+const WORKERS: usize = 2;
 
-mod captions;
-mod ffmpeg_babysitter;
-mod media_helpers; // for linting reasons // ditto
+/// how long running jobs get to finish when we're asked to stop, unless `SHUTDOWN_TIMEOUT` says otherwise
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
 
 #[tokio::main]
 async fn main() {
@@ -47,6 +35,32 @@ async fn main() {
     let intents = serenity::GatewayIntents::non_privileged()
         | serenity::GatewayIntents::GUILD_MESSAGES
         | serenity::GatewayIntents::MESSAGE_CONTENT;
+    // shared between discord and anything else that wants to run jobs
+    let queue = queue::JobQueue::new(WORKERS);
+    // comma separated role ids that get to skip ahead in the queue
+    let priority_roles: HashSet<serenity::RoleId> = std::env::var("PRIORITY_ROLES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<u64>().ok())
+        .filter(|id| *id != 0)
+        .map(serenity::RoleId::new)
+        .collect();
+    // jobs that were still waiting when we last went down
+    let journal = std::sync::Arc::new(artifice::journal::Journal::open(
+        std::env::var("JOURNAL_PATH")
+            .unwrap_or_else(|_| artifice::journal::DEFAULT_JOURNAL_PATH.to_string()),
+    ));
+    #[cfg(feature = "http")]
+    {
+        let queue = queue.clone();
+        tokio::spawn(async move {
+            if let Err(err) = artifice::http_api::serve(queue).await {
+                tracing::error!("HTTP API died: {}", err);
+            }
+        });
+    }
+    // the discord side gets the original, this one is for shutting down
+    let shutdown_queue = queue.clone();
     let framework = poise::Framework::<Data, Error>::builder()
         .options(poise::FrameworkOptions {
             commands: commands::commands(),
@@ -57,21 +71,65 @@ async fn main() {
                 }
             },
             owners: HashSet::from([415004648555151380.into()]),
+            event_handler: |ctx, event, framework, data| {
+                Box::pin(commands::event_handler(ctx, event, framework, data))
+            },
             ..Default::default()
         })
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {
-                    queue: queue::JobQueue::default(),
-                    job_semaphore: tokio::sync::Semaphore::new(2),
-                })
+                let data = Data {
+                    queue,
+                    priority_roles,
+                    journal,
+                    media_cache: MediaCache::new(SearchLimits::get().depth),
+                };
+                commands::resume_jobs(ctx.http.clone(), &data);
+                Ok(data)
             })
         })
         .build();
 
-    let client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .await;
-    client.unwrap().start().await.unwrap();
+        .await
+        .unwrap();
+
+    let shard_manager = client.shard_manager.clone();
+    let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("Shutting down, letting running jobs finish...");
+        shutdown_queue.close();
+        let cancelled = shutdown_queue.drain(shutdown_timeout).await;
+        if cancelled > 0 {
+            tracing::warn!("Cancelled {} jobs that didn't finish in time", cancelled);
+        }
+        shard_manager.shutdown_all().await;
+    });
+
+    client.start().await.unwrap();
+    tracing::info!("Artifice has stopped.");
+}
+
+/// wait for ctrl-c, or SIGTERM from docker and friends
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("can't listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
 }
@@ -0,0 +1,192 @@
+// remembering which recent messages had media, from the messages the gateway sends us anyway.
+// most of the time that means finding media doesn't need to ask discord for history at all.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use poise::serenity_prelude::{ChannelId, Message, MessageId, MessageUpdateEvent};
+
+use crate::media_helpers::might_have_media;
+
+/// the most channels we keep track of, the quietest get forgotten first
+pub const MAX_CHANNELS: usize = 1000;
+
+/// what we've seen in one channel
+struct ChannelMedia {
+    /// every message that's gone by since we started watching, oldest first,
+    /// as far back as a search from the newest one would go
+    ids: VecDeque<MessageId>,
+    /// the ones that might have media, oldest first
+    messages: VecDeque<Message>,
+    last_active: Instant,
+}
+
+/// what the cache has for a search
+pub struct Recent {
+    /// messages that might have media, newest first
+    pub messages: Vec<Message>,
+    /// how many messages from before the search started we watched go by,
+    /// `messages` already covers all of them
+    pub seen: u32,
+    /// the oldest of those, history fetches can pick up from there
+    pub oldest: Option<MessageId>,
+    /// whether we've watched the whole search depth go by,
+    /// so if there's nothing here there's nothing in the history either
+    pub complete: bool,
+}
+
+/// recent messages with media in every channel we can see
+pub struct MediaCache {
+    /// how many messages back a search goes
+    depth: u32,
+    channels: Mutex<HashMap<ChannelId, ChannelMedia>>,
+}
+
+impl MediaCache {
+    pub fn new(depth: u32) -> Self {
+        MediaCache {
+            depth,
+            channels: Mutex::default(),
+        }
+    }
+
+    fn channels(&self) -> std::sync::MutexGuard<'_, HashMap<ChannelId, ChannelMedia>> {
+        // nothing in here can be left half done, so a panic elsewhere doesn't matter
+        self.channels
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// a message was sent
+    pub fn message_created(&self, message: &Message) {
+        let mut channels = self.channels();
+        if !channels.contains_key(&message.channel_id) && channels.len() >= MAX_CHANNELS {
+            // make room by forgetting whoever's been quiet the longest
+            let quietest = channels
+                .iter()
+                .min_by_key(|(_, channel)| channel.last_active)
+                .map(|(id, _)| *id);
+            if let Some(quietest) = quietest {
+                channels.remove(&quietest);
+            }
+        }
+        let channel = channels
+            .entry(message.channel_id)
+            .or_insert_with(|| ChannelMedia {
+                ids: VecDeque::new(),
+                messages: VecDeque::new(),
+                last_active: Instant::now(),
+            });
+        channel.last_active = Instant::now();
+        channel.ids.push_back(message.id);
+        if might_have_media(message) {
+            channel.messages.push_back(message.clone());
+        }
+        // anything further back than a search goes is no use to us
+        while channel.ids.len() > self.depth as usize {
+            channel.ids.pop_front();
+        }
+        while channel
+            .messages
+            .front()
+            .is_some_and(|old| channel.ids.front().is_some_and(|oldest| old.id < *oldest))
+        {
+            channel.messages.pop_front();
+        }
+    }
+
+    /// a message was edited, or discord got round to embedding its links
+    pub fn message_updated(&self, event: &MessageUpdateEvent) {
+        let mut channels = self.channels();
+        let Some(channel) = channels.get_mut(&event.channel_id) else {
+            return;
+        };
+        if let Some(message) = channel
+            .messages
+            .iter_mut()
+            .find(|message| message.id == event.id)
+        {
+            event.apply_to_message(message);
+        }
+    }
+
+    /// messages were deleted
+    pub fn messages_deleted(&self, channel_id: ChannelId, deleted: &[MessageId]) {
+        let mut channels = self.channels();
+        if let Some(channel) = channels.get_mut(&channel_id) {
+            // gone from the history too, so they don't count towards the search depth
+            channel.ids.retain(|id| !deleted.contains(id));
+            channel
+                .messages
+                .retain(|message| !deleted.contains(&message.id));
+        }
+    }
+
+    /// messages in `channel_id` from before `before` that might have media.
+    /// `None` if we haven't seen anything there yet.
+    pub fn recent(&self, channel_id: ChannelId, before: MessageId) -> Option<Recent> {
+        let channels = self.channels();
+        let channel = channels.get(&channel_id)?;
+        // only what came before the search counts, whatever's been said since doesn't
+        let mut earlier = channel.ids.iter().filter(|id| **id < before);
+        let oldest = earlier.next().copied();
+        let seen = oldest.map_or(0, |_| earlier.count() as u32 + 1);
+        Some(Recent {
+            messages: channel
+                .messages
+                .iter()
+                .rev()
+                .filter(|message| message.id < before)
+                .cloned()
+                .collect(),
+            seen,
+            oldest,
+            complete: seen >= self.depth,
+        })
+    }
+}
+
+#[test]
+fn media_cache_test() {
+    let cache = MediaCache::new(3);
+    let channel = ChannelId::new(1);
+    let send = |id: u64, content: &str| {
+        let mut message = Message::default();
+        message.id = MessageId::new(id);
+        message.channel_id = channel;
+        message.content = content.to_string();
+        cache.message_created(&message);
+    };
+    let ids = |recent: Recent| {
+        recent
+            .messages
+            .iter()
+            .map(|message| message.id.get())
+            .collect::<Vec<_>>()
+    };
+
+    assert!(cache.recent(channel, MessageId::new(100)).is_none());
+    send(1, "https://example.com/cat.png");
+    // emoji in the middle of talking aren't media, on their own they are
+    send(2, "just talking <:kek:123>");
+    let recent = cache.recent(channel, MessageId::new(100)).unwrap();
+    // haven't seen a whole search worth of messages yet
+    assert!(!recent.complete);
+    assert_eq!(ids(recent), vec![1]);
+
+    send(3, "<:kek:123>");
+    send(4, "more talking");
+    let recent = cache.recent(channel, MessageId::new(100)).unwrap();
+    assert!(recent.complete);
+    // the link fell out of the search depth
+    assert_eq!(ids(recent), vec![3]);
+    // nothing from after the command counts, and there isn't a whole search worth before it
+    let recent = cache.recent(channel, MessageId::new(3)).unwrap();
+    assert!(!recent.complete);
+    assert_eq!((recent.seen, recent.oldest), (1, Some(MessageId::new(2))));
+    assert!(ids(recent).is_empty());
+
+    cache.messages_deleted(channel, &[MessageId::new(3)]);
+    assert!(ids(cache.recent(channel, MessageId::new(100)).unwrap()).is_empty());
+}
@@ -3,19 +3,30 @@
 extern crate reqwest;
 use std::fs::File;
 use std::io::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
 use std::{ffi::OsStr, path::PathBuf};
 
 use ffmpeg_sidecar::command::FfmpegCommand;
 
-use poise::serenity_prelude::{MessageId, MessagePagination};
+use poise::serenity_prelude::{
+    MessageId, MessagePagination, PartialGuild, StickerFormatType, StickerItem, User,
+};
+use poise::ChoiceParameter as _;
 use rand::Rng;
 use regex::Regex;
+use reqwest::Url;
 use tempfile::TempDir;
 use tracing::info;
 
-use crate::ffmpeg_babysitter::ffbabysit;
+use crate::ffmpeg_babysitter::{ffbabysit, spawn_limited, JobControl};
+use crate::filter_graph::{Filter, FilterGraph};
+use crate::job::MAX_SIZE;
+use crate::resolvers::resolve_page;
+use crate::sniff::{identify, sniff_file};
 
-use crate::Context;
+use crate::{ArtificeError, Context};
 
 use poise::serenity_prelude::model::prelude::Message;
 
@@ -31,13 +42,28 @@ pub struct Media {
     pub output_tempfile: Option<TempFileHolder>,
 }
 
+impl Media {
+    /// turn the output of an operation into the input of the next one.
+    /// the old input file is dropped (and deleted) here.
+    pub fn into_next_input(self) -> Result<Media, crate::Error> {
+        let output = self.output_tempfile.ok_or_else(|| {
+            ArtificeError::Internal("Operation did not produce an output file.".into())
+        })?;
+        Ok(Media {
+            media_type: self.media_type,
+            file_path: output,
+            output_tempfile: None,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct TempFileHolder {
     pub dir: TempDir,
     pub path: PathBuf,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub enum MediaType {
     Video,
     Gif,
@@ -46,9 +72,31 @@ pub enum MediaType {
     Unknown,
 }
 
-pub enum FFprobeError {
-    UnknownSize,
-    Other(String)
+impl MediaType {
+    /// best guess from a file extension, for files that didn't come with a content type
+    pub fn from_extension(extension: &str) -> Option<MediaType> {
+        match extension.to_lowercase().as_str() {
+            "mp4" | "webm" | "mov" | "mkv" | "avi" => Some(MediaType::Video),
+            "gif" | "apng" => Some(MediaType::Gif),
+            "png" | "jpg" | "jpeg" | "webp" | "bmp" | "tiff" | "avif" | "heic" => {
+                Some(MediaType::Image)
+            }
+            "mp3" | "wav" | "ogg" | "flac" | "m4a" | "opus" => Some(MediaType::Audio),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for MediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MediaType::Video => "video",
+            MediaType::Gif => "gif",
+            MediaType::Image => "image",
+            MediaType::Audio => "audio",
+            MediaType::Unknown => "unknown",
+        })
+    }
 }
 
 impl MediaType {
@@ -79,80 +127,58 @@ impl MediaType {
     }
 }
 
-pub fn resize_media(input: Media, mut x_size: u16, y_size: u16) -> Result<Media, crate::Error> {
-    // This function takes in a media file, and resizes it to be of certain dimensions.
-
+/// the filters for a resize. a width of 0 keeps the aspect ratio.
+pub fn resize_filters(x_size: u16, y_size: u16) -> Vec<Filter> {
     // TODO: fix transparency on gifs (currently adds a white background)
 
-    // Make sure the media isn't a audio file, because we cant resize that.
-
-    if input.media_type == MediaType::Audio {
-        // Cant resize audio.
-        return Err("Cannot resize a audio file.".into());
-    }
     // h264 gets very angry if the sizes are not divisible by 2, therefore we must check
-    
-    // make sure that sucker is even
-    if (x_size % 2) == 1 {
-        //odd! add one.
-        x_size += 1;
-    }
-    if y_size != 0 && (y_size % 2) == 1 {
-        //odd! add one.
-        x_size += 1;
-    }
-
-    // if the x size is 0, we will automatically rescale using the y size and aspect ratio.
-
-    if x_size == 0 {
-        // need to calculate new size.
-        // get the size of input media
-        let (x_media_size, y_media_size): (i64, i64) = match get_pixel_size(&input) {
-            Ok(ok) => ok,
-            Err(err) => match err {
-                FFprobeError::UnknownSize => return Err("Could not determine file dimensions.".into()),
-                FFprobeError::Other(ouch) => return Err(ouch.into()),
-            },
-        };
-        let aspect_ratio: f32 = x_media_size as f32 / y_media_size as f32;
 
-        // now multiply and round to get our new y size
-        x_size = (aspect_ratio * y_size as f32).round() as u16;
-        // make sure that sucker is even
-        if (x_size % 2) == 1 {
-            //odd! add one.
-            x_size += 1;
-        }
+    // make sure that sucker is even, odd sizes get one added.
+    let even = |size: u16| {
+        let size = i32::from(size);
+        size + size % 2
     };
 
+    let height = even(y_size);
+    // if the x size is 0, ffmpeg works it out from the y size and aspect ratio.
+    // kept even like the rest, and no wider than we'd let anyone ask for,
+    // so a really wide image can't turn into a gigantic frame.
+    let width = if x_size == 0 {
+        format!("max(2,min({},2*trunc(iw*{}/ih/2)))", MAX_SIZE, height)
+    } else {
+        even(x_size).to_string()
+    };
 
-    // Media is of a good size, now to process it.
+    vec![Filter::new("scale").arg(width).arg(height)]
+}
 
+/// run `filters` over the video of `input` in a single ffmpeg pass
+pub fn filter_media(
+    input: Media,
+    filters: Vec<Filter>,
+    control: &JobControl,
+) -> Result<Media, crate::Error> {
     // get the extension of the input file
-    let extension = input.file_path.path.extension().unwrap();
+    let extension = input
+        .file_path
+        .path
+        .extension()
+        .ok_or_else(|| ArtificeError::Internal("Media has no file extension.".into()))?;
 
     // create a tempfile to store the output.
     let dir = new_temp_media(extension);
 
-    // Do the actual resizing.
-    // every arg gets a separate line for readability instead of an array.
-
-    let output = FfmpegCommand::new()
+    let mut command = FfmpegCommand::new();
+    command
         .hwaccel(std::env::var("HW_ACCEL").unwrap_or("none".to_string()))
-        .input(input.file_path.path.as_path().to_str().unwrap()) // input file
-        .args([
-            // set the dimensions
-            "-vf",
-            &format!("scale={}:{}", x_size, y_size),
-        ])
+        .input(path_str(&input.file_path.path)?) // input file
+        .args(FilterGraph::simple(filters).to_args())
         .codec_audio("copy") // copy audio codec
-        //.output(tempfile_path.to_str().unwrap()) // where is it going?
-        .output(dir.path.to_str().unwrap())
-        .spawn()
-        .unwrap(); // run that sucker
+        .output(path_str(&dir.path)?);
+    let output = spawn_limited(&mut command)?; // run that sucker
 
     // wait for that to finish
-    ffbabysit(output)?;
+    ffbabysit(output, control)?;
 
     // now build our output
 
@@ -198,229 +224,708 @@ pub fn new_temp_dir() -> TempDir {
     tempfile::tempdir().unwrap()
 }
 
-/// get the screen size of a media file
-/// returns (x,y)
-pub fn get_pixel_size(input: &Media) -> Result<(i64, i64), FFprobeError> {
-    // ask ffprobe
-    let media_info = match ffprobe::ffprobe(&input.file_path.path) {
-        // all good
-        Ok(info) => info,
-        // something broke
-        Err(err) => {
-            tracing::info!("ffprobe size failed!");
-            return Err(FFprobeError::UnknownSize);
+/// `path` as a str for handing to ffmpeg, our temp files are always valid UTF-8
+pub fn path_str(path: &std::path::Path) -> crate::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| ArtificeError::Internal(format!("{} is not valid UTF-8", path.display())))
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct UrlAndMediaType {
+    pub url: String,
+    pub media_type: MediaType,
+    /// in bytes, if we know it before downloading
+    pub size: Option<u64>,
+}
+
+/// a link to media, as a command argument. only parses from http(s) urls,
+/// so prefix commands can leave it out without eating the next argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaUrl(pub String);
+
+/// what's wrong with something that isn't a `MediaUrl`
+#[derive(Debug)]
+pub struct NotAUrl;
+
+impl std::fmt::Display for NotAUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("That isn't a http(s) link.")
+    }
+}
+
+impl std::error::Error for NotAUrl {}
+
+impl std::str::FromStr for MediaUrl {
+    type Err = NotAUrl;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        // discord lets people wrap links in <> to stop them embedding
+        let text = text.trim().trim_start_matches('<').trim_end_matches('>');
+        match reqwest::Url::parse(text) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(MediaUrl(url.into())),
+            _ => Err(NotAUrl),
         }
-    };
+    }
+}
 
-    // now pull out the x and y
+/// a custom emoji, like `<:name:id>` or `<a:name:id>` for animated ones.
+/// parses the same way as `MediaUrl`, so it can sit in front of other arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CustomEmoji {
+    pub id: u64,
+    pub animated: bool,
+}
 
-    let size_x: i64 = media_info.streams[0]
-        .width
-        .expect("Media had unknown x size!");
-    let size_y: i64 = media_info.streams[0]
-        .height
-        .expect("Media had unknown y size!");
+/// what's wrong with something that isn't a `CustomEmoji`
+#[derive(Debug)]
+pub struct NotAnEmoji;
 
-    Ok((size_x, size_y))
+impl std::fmt::Display for NotAnEmoji {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("That isn't a custom emoji.")
+    }
 }
 
-pub struct UrlAndMediaType {
-    url: String,
-    media_type: MediaType,
+impl std::error::Error for NotAnEmoji {}
+
+impl std::str::FromStr for CustomEmoji {
+    type Err = NotAnEmoji;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match emojis_in(text.trim()).as_slice() {
+            [emoji] => Ok(*emoji),
+            _ => Err(NotAnEmoji),
+        }
+    }
 }
 
-impl UrlAndMediaType {
-    pub fn default() -> Self {
+impl CustomEmoji {
+    /// where discord keeps the picture. animated ones come as gifs.
+    pub fn media(&self) -> UrlAndMediaType {
+        let (extension, media_type) = if self.animated {
+            ("gif", MediaType::Gif)
+        } else {
+            ("png", MediaType::Image)
+        };
         UrlAndMediaType {
-            url: String::new(),
-            media_type: MediaType::Unknown,
+            url: format!("https://cdn.discordapp.com/emojis/{}.{}", self.id, extension),
+            media_type,
+            size: None,
         }
     }
 }
 
-// looks for a media file in the chat history. (does not download it)
-pub async fn find_media(ctx: Context<'_>) -> crate::Result<Option<UrlAndMediaType>> {
-    // TODO: gifs from tenor.
+/// every custom emoji in `content`, in order
+fn emojis_in(content: &str) -> Vec<CustomEmoji> {
+    static FINDER: OnceLock<Regex> = OnceLock::new();
+    let finder = FINDER.get_or_init(|| Regex::new(r"<(a?):\w+:(\d+)>").unwrap());
+    finder
+        .captures_iter(content)
+        .filter_map(|emoji| {
+            Some(CustomEmoji {
+                id: emoji[2].parse().ok()?,
+                animated: !emoji[1].is_empty(),
+            })
+        })
+        .collect()
+}
+
+/// the custom emoji in `content` if that's all there is to it, otherwise nothing.
+/// emoji in the middle of a sentence are just talking, not something to edit.
+fn emoji_only(content: &str) -> Vec<CustomEmoji> {
+    static FINDER: OnceLock<Regex> = OnceLock::new();
+    let finder = FINDER.get_or_init(|| Regex::new(r"<a?:\w+:\d+>").unwrap());
+    if !finder.replace_all(content, "").trim().is_empty() {
+        return Vec::new();
+    }
+    emojis_in(content)
+}
+
+/// where discord keeps a sticker, if it's something we can draw
+fn sticker_media(sticker: &StickerItem) -> Option<UrlAndMediaType> {
+    let (url, media_type) = match sticker.format_type {
+        StickerFormatType::Png => (
+            format!("https://cdn.discordapp.com/stickers/{}.png", sticker.id),
+            MediaType::Image,
+        ),
+        // apngs still end in .png
+        StickerFormatType::Apng => (
+            format!("https://cdn.discordapp.com/stickers/{}.png", sticker.id),
+            MediaType::Gif,
+        ),
+        // gif stickers only come from the media proxy
+        StickerFormatType::Gif => (
+            format!("https://media.discordapp.net/stickers/{}.gif", sticker.id),
+            MediaType::Gif,
+        ),
+        // lottie is animated json, nothing ffmpeg can draw
+        _ => return None,
+    };
+    Some(UrlAndMediaType {
+        url,
+        media_type,
+        size: None,
+    })
+}
+
+/// one of a server's pictures
+#[derive(Debug, poise::ChoiceParameter, PartialEq, Eq, Clone, Copy)]
+pub enum ServerImage {
+    #[name = "icon"]
+    Icon,
+    #[name = "banner"]
+    Banner,
+}
+
+/// a picture off discord's cdn, like `avatars/<user id>`. `a_` hashes are animated.
+fn cdn_image(path: &str, hash: &str) -> UrlAndMediaType {
+    let (extension, media_type) = if hash.starts_with("a_") {
+        ("gif", MediaType::Gif)
+    } else {
+        ("png", MediaType::Image)
+    };
+    UrlAndMediaType {
+        url: format!(
+            "https://cdn.discordapp.com/{}/{}.{}?size=1024",
+            path, hash, extension
+        ),
+        media_type,
+        size: None,
+    }
+}
+
+/// someone's avatar, animated if theirs is
+pub fn avatar_media(user: &User) -> UrlAndMediaType {
+    match &user.avatar {
+        Some(hash) => cdn_image(&format!("avatars/{}", user.id), &hash.to_string()),
+        // never set one, so they get one of the default blobs
+        None => UrlAndMediaType {
+            url: user.default_avatar_url(),
+            media_type: MediaType::Image,
+            size: None,
+        },
+    }
+}
+
+/// the server's icon or banner, if it has one
+pub fn server_media(guild: &PartialGuild, image: ServerImage) -> crate::Result<UrlAndMediaType> {
+    let (hash, path) = match image {
+        ServerImage::Icon => (guild.icon.as_ref().map(ToString::to_string), "icons"),
+        ServerImage::Banner => (guild.banner.as_ref().map(ToString::to_string), "banners"),
+    };
+    let hash = hash.ok_or_else(|| {
+        ArtificeError::Invalid(format!("This server doesn't have a {}.", image.name()))
+    })?;
+    Ok(cdn_image(&format!("{}/{}", path, guild.id), &hash))
+}
+
+/// what a command was pointed at, instead of whatever's in the channel
+#[derive(Debug, Clone)]
+pub enum MediaTarget<'a> {
+    Message(&'a Message),
+    Url(MediaUrl),
+    Emoji(CustomEmoji),
+    User(&'a User),
+    Server(ServerImage),
+}
+
+impl<'a> MediaTarget<'a> {
+    /// whichever of a command's `url`, `emoji`, `user` and `server` arguments got filled in
+    pub fn from_args(
+        url: Option<MediaUrl>,
+        emoji: Option<CustomEmoji>,
+        user: Option<&'a User>,
+        server: Option<ServerImage>,
+    ) -> Option<Self> {
+        url.map(MediaTarget::Url)
+            .or(emoji.map(MediaTarget::Emoji))
+            .or(user.map(MediaTarget::User))
+            .or(server.map(MediaTarget::Server))
+    }
+}
+
+/// the most files we will pick up from a single message,
+/// since that's also the most we can send back in one reply.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+// looks for media files in the chat history. (does not download them)
+// every usable attachment on the first message with media is returned, in order.
+// if the command was aimed at a specific message or link, only that gets looked at.
+pub async fn find_media(
+    ctx: Context<'_>,
+    target: Option<MediaTarget<'_>>,
+) -> crate::Result<Vec<UrlAndMediaType>> {
+    // pointed at a message? use that one and nothing else.
+    // for prefix commands, replying to a message counts as pointing at it.
+    let (invoked_by, replied_to) = match ctx {
+        poise::Context::Prefix(prefix) => {
+            (Some(prefix.msg), prefix.msg.referenced_message.as_deref())
+        }
+        poise::Context::Application(_) => (None, None),
+    };
+    match target.or(replied_to.map(MediaTarget::Message)) {
+        Some(MediaTarget::Url(url)) => {
+            info!("Looking for media at {}...", url.0);
+            crate::net::check(&url.0)?;
+            let found = match media_at(&url.0, Some(probe_deadline())).await {
+                Some(found) => Some(found),
+                // might be a page with the media on it
+                None => resolve_page(&url.0, true).await?,
+            };
+            let found = found.ok_or_else(|| {
+                ArtificeError::Invalid("That link doesn't point at any media.".into())
+            })?;
+            return Ok(vec![found]);
+        }
+        Some(MediaTarget::Emoji(emoji)) => {
+            info!("Using emoji {}...", emoji.id);
+            return Ok(vec![emoji.media()]);
+        }
+        Some(MediaTarget::User(user)) => {
+            info!("Using {}'s avatar...", user.name);
+            return Ok(vec![avatar_media(user)]);
+        }
+        Some(MediaTarget::Server(image)) => {
+            let guild = ctx.partial_guild().await.ok_or_else(|| {
+                ArtificeError::Invalid("That only works in a server.".into())
+            })?;
+            info!("Using the {} of {}...", image.name(), guild.name);
+            return Ok(vec![server_media(&guild, image)?]);
+        }
+        Some(MediaTarget::Message(target)) => {
+            info!("Looking for media in message {}...", target.id);
+            let found = media_in(target, Look::target()).await?;
+            if found.is_empty() {
+                return Err(ArtificeError::Invalid(
+                    "No media found in that message.".into(),
+                ));
+            }
+            return Ok(found);
+        }
+        None => {}
+    }
+
+    // the command itself might have come with something
+    if let Some(message) = invoked_by {
+        let found = media_in(message, Look::invoker()).await?;
+        if !found.is_empty() {
+            return Ok(found);
+        }
+    }
+
     info!("Looking for media...");
     // now we shall take that mf context and look for some media
     let channel_id: poise::serenity_prelude::model::prelude::ChannelId = ctx.channel_id();
     let http = ctx.http();
     // get the message id that this context came from
     let start: MessageId = ctx.id().into();
+    let mut found: Vec<UrlAndMediaType> = Vec::new();
+    let mut number_checked: u32 = 0;
+    let mut search_params: MessagePagination = MessagePagination::Before(start);
+
+    // anything we watched go past is free to look through
+    if let Some(recent) = ctx.data().media_cache.recent(channel_id, start) {
+        info!("Checking {} cached message(s)...", recent.messages.len());
+        for message in &recent.messages {
+            found = media_in(message, Look::Skim).await?;
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+        if recent.complete {
+            // we've seen the whole search depth go by, so asking discord won't turn up anything new
+            info!("No media found.");
+            return Ok(found);
+        }
+        // those are already checked, so discord only needs to show us what came before them
+        if let Some(oldest) = recent.oldest {
+            search_params = MessagePagination::Before(oldest);
+            number_checked = recent.seen;
+        }
+    }
 
     // we are going to loop over messages until we find media, with a limit of messages checked.
 
-    const PAGE_SIZE: u8 = 5;
-    const READ_LIMIT: u32 = 50;
-    let mut number_checked: u32 = 0;
-    let mut search_params: MessagePagination = MessagePagination::Before(start);
+    let SearchLimits {
+        page_size,
+        depth: read_limit,
+    } = SearchLimits::get();
     let mut messages: Vec<Message>;
-    let mut found_url: UrlAndMediaType = UrlAndMediaType::default();
 
     loop {
-        if number_checked >= READ_LIMIT {
+        if number_checked >= read_limit {
             break;
         }
         // grab some messages
-        info!("Looking for media, Pulling {} messages...", PAGE_SIZE);
+        info!("Looking for media, Pulling {} messages...", page_size);
         messages = http
-            .get_messages(channel_id, Some(search_params), Some(PAGE_SIZE))
+            .get_messages(channel_id, Some(search_params), Some(page_size))
             .await?;
 
         for message in &messages {
-            // here's our list of checks:
-            // Attachments
-            // Embeds
-
-            // Does this message have any attachments?
-
-            // we only care if the message has a SINGLE attachment.
-            if message.attachments.len() == 1 {
-                info!("Found an attachment...");
-                // wowie boys we got medias
-                let find_type = message
-                    .attachments
-                    .first()
-                    .unwrap()
-                    .content_type
-                    .as_ref()
-                    .unwrap()
-                    .clone();
-
-                // is this a thing we can actually use
-                let maybe_media_type = MediaType::from(find_type);
-                // can we use it?
-                if maybe_media_type.is_none() {
-                    // nuh uh
-                    info!("...but it was something we couldn't use.");
-                    continue;
-                }
-                info!("Good media file!");
-                // cool we can use it!
-                found_url = UrlAndMediaType {
-                    url: message.attachments.first().unwrap().url.clone(),
-                    media_type: maybe_media_type.unwrap(),
-                };
+            found = media_in(message, Look::Skim).await?;
+            if !found.is_empty() {
                 break;
             }
-
-            // does this message have any embeds?
-            if !message.embeds.is_empty() {
-                info!("Found embeds...");
-                // embeds present!
-
-                // anything we can use?
-                for embed in message.embeds.clone() {
-                    if let Some(n) = embed.kind {
-                        match n.as_str() {
-                            "image" => {
-                                info!("Found an embedded image!");
-                                found_url = UrlAndMediaType {
-                                    url: embed.url.unwrap(),
-                                    media_type: MediaType::Image,
-                                }
-                            }
-                            "video" => {
-                                info!("Found an embedded video...");
-                                
-                                // make sure this is a direct link to media, and not just some random
-                                // website page (ie YouTube)
-                                
-                                let context_length = reqwest::get(embed.url.clone().unwrap()).await?.content_length();
-                                
-                                if context_length.is_none() {
-                                    // this isn't a direct file.
-                                    info!("...But it wasn't a file url.");
-                                    continue;
-                                }
-                                info!("...Looks like a proper file!");
-
-                                found_url = UrlAndMediaType {
-                                    url: embed.url.unwrap(),
-                                    media_type: MediaType::Video,
-                                }
-                            }
-                            "gifv" => {
-                                info!("Found an embedded gifv...");
-                                // tenor gif...
-                                // "download" the gif (really its the html of the page)
-
-                                let mut tenor_string: String = String::new();
-                                info!("Attempting to extract link...");
-                                let mut request = reqwest::get(embed.url.unwrap()).await?;
-                                loop {
-                                    let check = request.chunk().await?;
-                                    if check.is_none() {
-                                        info!("Unable to find link.");
-                                        info!("\n\n\n\n{}\n\n\n\n", tenor_string);
-                                        // we've failed to find the link, give up.
-                                        break;
-                                    }
-                                    tenor_string += &String::from_utf8_lossy(&check.unwrap())
-                                        .replace("\\u002F", "/"); // probably a bad idea lmao
-                                                                  // is the part we're looking for in here?
-                                                                  // first check to make sure we have that entire section
-                                    let finder = Regex::new(
-                                        r#"<meta itemProp="contentUrl" content="(?<url>[^"]+)">"#,
-                                    )
-                                    .unwrap();
-
-                                    // look
-                                    let looking_glass = finder.captures(&tenor_string);
-
-                                    if looking_glass.is_some() {
-                                        // gotcha!
-                                        info!("Found it!");
-                                        // pull out the url
-                                        found_url = UrlAndMediaType {
-                                            url: looking_glass
-                                                .unwrap()
-                                                .name("url")
-                                                .unwrap()
-                                                .as_str()
-                                                .to_string(),
-                                            media_type: MediaType::Gif,
-                                        };
-                                        break;
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-
-                // if we've found something, stop here.
-                if found_url.media_type != MediaType::Unknown {
-                    // got something!
-                    break;
-                }
-            }
         }
 
         // if we've found something, stop here.
-        if found_url.media_type != MediaType::Unknown {
+        if !found.is_empty() {
             // got something!
             break;
         }
 
         // didnt find anything, try again.
         // update the search start point
-        search_params = MessagePagination::Before(messages.last().unwrap().id);
+        let Some(last) = messages.last() else {
+            // ran out of channel
+            break;
+        };
+        search_params = MessagePagination::Before(last.id);
         // count the loop
-        number_checked += PAGE_SIZE as u32
+        number_checked += page_size as u32
     }
 
     // got anything?
-    if found_url.media_type == MediaType::Unknown {
+    if found.is_empty() {
         // no :(
-        return Ok(None);
+        info!("No media found.");
+    }
+
+    // return the urls to the files
+    Ok(found)
+}
+
+/// how far back to look for media, from the environment.
+/// `SEARCH_DEPTH` is how many messages, `SEARCH_PAGE_SIZE` how many we ask discord for at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct SearchLimits {
+    /// at most 100, discord won't give us more than that at once
+    pub page_size: u8,
+    pub depth: u32,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits {
+            page_size: 5,
+            depth: 50,
+        }
+    }
+}
+
+impl SearchLimits {
+    pub fn from_env() -> Self {
+        let default = SearchLimits::default();
+        SearchLimits {
+            page_size: std::env::var("SEARCH_PAGE_SIZE")
+                .ok()
+                // anything over 100 means "as many as we can get", not "doesn't count"
+                .and_then(|value| value.parse::<u64>().ok())
+                .map_or(default.page_size, |size| size.clamp(1, 100) as u8),
+            depth: std::env::var("SEARCH_DEPTH")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default.depth),
+        }
+    }
+
+    /// read once, they don't change while we're running
+    pub fn get() -> SearchLimits {
+        static LIMITS: OnceLock<SearchLimits> = OnceLock::new();
+        *LIMITS.get_or_init(SearchLimits::from_env)
     }
+}
 
-    // found something!
+/// whether `message` could have anything `media_in` would find, without asking anyone
+pub fn might_have_media(message: &Message) -> bool {
+    !message.attachments.is_empty()
+        || !message.sticker_items.is_empty()
+        || !message.embeds.is_empty()
+        || message.content.contains("http")
+        || !emoji_only(&message.content).is_empty()
+}
+
+/// how long we'll spend asking around about the links in one message
+const PROBE_BUDGET: Duration = Duration::from_secs(10);
+
+fn probe_deadline() -> tokio::time::Instant {
+    tokio::time::Instant::now() + PROBE_BUDGET
+}
 
-    // return the url to the file
-    Ok(Some(found_url))
+/// how closely to look at a message
+#[derive(Debug, Clone, Copy)]
+enum Look {
+    /// the message the command was pointed at: ask whoever hosts its links until the deadline,
+    /// and any custom emoji in it will do
+    Target(tokio::time::Instant),
+    /// the message with the command in it: links get asked about too,
+    /// but emoji are just part of what they typed
+    Invoker(tokio::time::Instant),
+    /// history we're skimming: links go by what they look like,
+    /// and emoji only count when that's all the message is
+    Skim,
+}
+
+impl Look {
+    fn target() -> Look {
+        Look::Target(probe_deadline())
+    }
+
+    fn invoker() -> Look {
+        Look::Invoker(probe_deadline())
+    }
+
+    /// how long we've got to ask about links, if we're asking at all
+    fn deadline(self) -> Option<tokio::time::Instant> {
+        match self {
+            Look::Target(deadline) | Look::Invoker(deadline) => Some(deadline),
+            Look::Skim => None,
+        }
+    }
+}
+
+// the media on a single message. attachments win over embeds.
+async fn media_in(message: &Message, look: Look) -> crate::Result<Vec<UrlAndMediaType>> {
+    let mut found: Vec<UrlAndMediaType> = Vec::new();
+
+    // here's our list of checks:
+    // Attachments
+    // Stickers
+    // Embeds
+    // Emoji and links in the message itself
+
+    // Does this message have any attachments?
+    if !message.attachments.is_empty() {
+        info!("Found {} attachment(s)...", message.attachments.len());
+        // wowie boys we got medias
+        // every usable attachment gets picked up, up to what we can send back.
+        for attachment in message.attachments.iter().take(MAX_ATTACHMENTS) {
+            // is this a thing we can actually use
+            // discord doesn't always tell us, and isn't always right when it does.
+            // this is just a first guess, the file gets sniffed once it's downloaded.
+            let media_type = attachment
+                .content_type
+                .clone()
+                .and_then(MediaType::from)
+                .or_else(|| {
+                    std::path::Path::new(&attachment.filename)
+                        .extension()
+                        .and_then(OsStr::to_str)
+                        .and_then(MediaType::from_extension)
+                })
+                .unwrap_or(MediaType::Unknown);
+            if attachment.content_type.is_some() && media_type == MediaType::Unknown {
+                // it told us, and it's not media. nuh uh
+                info!("...but {} was something we couldn't use.", attachment.filename);
+                continue;
+            }
+            info!("Good media file!");
+            // cool we can use it!
+            found.push(UrlAndMediaType {
+                url: attachment.url.clone(),
+                media_type,
+                size: Some(attachment.size.into()),
+            });
+        }
+
+        // if we've found something, stop here.
+        if !found.is_empty() {
+            found.truncate(MAX_ATTACHMENTS);
+            return Ok(found);
+        }
+    }
+
+    // stickers are pictures too, as long as they aren't lottie
+    if !message.sticker_items.is_empty() {
+        info!("Found {} sticker(s)...", message.sticker_items.len());
+        found.extend(message.sticker_items.iter().filter_map(sticker_media));
+        if !found.is_empty() {
+            found.truncate(MAX_ATTACHMENTS);
+            return Ok(found);
+        }
+    }
+
+    // does this message have any embeds?
+    if !message.embeds.is_empty() {
+        info!("Found embeds...");
+        // embeds present!
+
+        // anything we can use?
+        for embed in message.embeds.clone() {
+            let Some(url) = embed.url else {
+                continue;
+            };
+            if let Some(n) = embed.kind {
+                match n.as_str() {
+                    "image" => {
+                        info!("Found an embedded image!");
+                        found.push(UrlAndMediaType {
+                            url,
+                            media_type: MediaType::Image,
+                            size: None,
+                        });
+                    }
+                    "video" | "gifv" => {
+                        info!("Found an embedded {}...", n);
+                        // a direct link to the file? then there's nothing to dig for
+                        if let Some(media) = media_at(&url, look.deadline()).await {
+                            info!("...Looks like a proper file!");
+                            found.push(media);
+                            continue;
+                        }
+                        // otherwise it's a page (tenor, imgur, youtube...) that might have it somewhere
+                        info!("Attempting to extract link...");
+                        // only load random sites if we were pointed at this message
+                        let any_site = look.deadline().is_some();
+                        match resolve_page(&url, any_site).await {
+                            Ok(Some(media)) => found.push(media),
+                            Ok(None) => info!("...But it wasn't a file url."),
+                            // one broken embed shouldn't stop us looking at the rest
+                            Err(err) => err.log(),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // custom emoji, which discord doesn't embed at all
+    if found.is_empty() {
+        let emojis = match look {
+            Look::Target(_) => emojis_in(&message.content),
+            Look::Invoker(_) | Look::Skim => emoji_only(&message.content),
+        };
+        found.extend(emojis.iter().map(CustomEmoji::media));
+    }
+
+    // plain links that discord didn't embed, or that had their embed taken away
+    if found.is_empty() {
+        for url in urls_in(&message.content).into_iter().take(MAX_ATTACHMENTS) {
+            if let Some(media) = media_at(url, look.deadline()).await {
+                info!("Found a link to some media!");
+                found.push(media);
+            }
+        }
+    }
+
+    found.truncate(MAX_ATTACHMENTS);
+    Ok(found)
+}
+
+/// every http(s) link in `content`
+fn urls_in(content: &str) -> Vec<&str> {
+    static FINDER: OnceLock<Regex> = OnceLock::new();
+    let finder = FINDER.get_or_init(|| Regex::new(r"https?://[^\s<>]+").unwrap());
+    finder
+        .find_iter(content)
+        // punctuation at the end is almost always the sentence, not the link
+        .map(|found| found.as_str().trim_end_matches(['.', ',', ')', '!', '?', '\'', '"']))
+        .collect()
+}
+
+/// ask whoever's hosting `url` what it is, without downloading it,
+/// if there's a `deadline` to do it by. `None` if it doesn't look like media.
+async fn media_at(url: &str, deadline: Option<tokio::time::Instant>) -> Option<UrlAndMediaType> {
+    // what the url itself looks like, for servers that won't tell us
+    let guess = Url::parse(url).ok().and_then(|parsed| {
+        Path::new(parsed.path())
+            .extension()
+            .and_then(OsStr::to_str)
+            .and_then(MediaType::from_extension)
+    });
+    let guessed = || {
+        guess.map(|media_type| UrlAndMediaType {
+            url: url.to_string(),
+            media_type,
+            size: None,
+        })
+    };
+    let Some(deadline) = deadline else {
+        return guessed();
+    };
+    // not somewhere we'd download from either
+    crate::net::check(url).ok()?;
+    let head = crate::net::client().head(url).send();
+    let head = match tokio::time::timeout_at(deadline, head).await {
+        Ok(Ok(resp)) => resp.error_for_status().ok(),
+        _ => None,
+    };
+    let Some(head) = head else {
+        // plenty of hosts don't do HEAD, and we might be out of time. go by the name
+        return guessed();
+    };
+    let header = |name| {
+        head.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    // the body of a HEAD is empty, so the length has to come from the header itself
+    let size = header(reqwest::header::CONTENT_LENGTH).and_then(|length| length.parse().ok());
+    let content_type = header(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default();
+    let media_type = match content_type {
+        "" | "application/octet-stream" | "binary/octet-stream" => guess,
+        content_type => MediaType::from(content_type.to_string()),
+    }?;
+    Some(UrlAndMediaType {
+        url: url.to_string(),
+        media_type,
+        size,
+    })
+}
+
+/// how much we're willing to download, from the environment.
+/// `MAX_DOWNLOAD_MB` caps the size of each file, `DOWNLOAD_TIMEOUT` is in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadLimits {
+    pub max_bytes: u64,
+    pub timeout: Duration,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        DownloadLimits {
+            max_bytes: 100 * 1024 * 1024,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl DownloadLimits {
+    pub fn from_env() -> Self {
+        let read = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let default = DownloadLimits::default();
+        DownloadLimits {
+            max_bytes: read("MAX_DOWNLOAD_MB")
+                .map_or(default.max_bytes, |mb| mb.saturating_mul(1024 * 1024)),
+            timeout: read("DOWNLOAD_TIMEOUT").map_or(default.timeout, Duration::from_secs),
+        }
+    }
+
+    /// read once, they don't change while we're running
+    pub fn get() -> DownloadLimits {
+        static LIMITS: OnceLock<DownloadLimits> = OnceLock::new();
+        *LIMITS.get_or_init(DownloadLimits::from_env)
+    }
+
+    /// turn away `size` bytes before we bother downloading them
+    pub fn check_size(&self, size: u64) -> crate::Result {
+        if size > self.max_bytes {
+            return Err(ArtificeError::TooLarge(format!(
+                "That file is too big, the limit is {}MB.",
+                self.max_bytes / (1024 * 1024)
+            )));
+        }
+        Ok(())
+    }
 }
 
 // download a media file!
@@ -428,41 +933,61 @@ pub async fn download_media(file: UrlAndMediaType) -> crate::Result<Option<Media
     // first, get a folder to store it
     let tempdir = new_temp_dir();
 
-    // now actually download the file
-    info!("Downloading a media file...");
-    info!("From: {}", file.url);
-    let resp = reqwest::get(file.url.clone()).await?;
-
     // split out the filename
     // TODO: cleanup?
     let filename = file
         .url
         .split('/')
-        .last()
-        .unwrap()
+        .next_back()
+        .unwrap_or_default()
         .split('?')
         .next()
-        .unwrap();
+        .unwrap_or_default();
+    let filename = if filename.is_empty() { "media" } else { filename };
 
     // Create the target file path within the folder
-    let file_path = tempdir.path().join(filename);
-    info!("{}", file_path.to_str().unwrap());
-
-    // Get the response body as bytes
-    let data = resp.bytes().await?;
+    let mut file_path = tempdir.path().join(filename);
+    info!("{}", file_path.display());
 
-    // Open the target file for writing
-    let mut out = File::create(file_path.clone())?;
-
-    // Copy downloaded data to the file
-    out.write_all(&data)?;
+    // now actually download the file
+    info!("Downloading a media file...");
+    info!("From: {}", file.url);
+    let url = crate::net::check(&file.url)?;
+    let limits = DownloadLimits::get();
+    tokio::time::timeout(limits.timeout, download_to(url, &file_path, limits))
+        .await
+        .map_err(|_| {
+            ArtificeError::DownloadFailed(format!(
+                "gave up on {} after {}s",
+                file.url,
+                limits.timeout.as_secs()
+            ))
+        })??;
 
     // in theory we have the file now.
     info!("Finished downloading {}", filename);
 
+    // ffmpeg picks what to write from the extension, so make sure there is one.
+    if file_path.extension().is_none() {
+        if let Some(sniffed) = sniff_file(&file_path)? {
+            let named = file_path.with_extension(sniffed.extension);
+            std::fs::rename(&file_path, &named)?;
+            file_path = named;
+        }
+    }
+
+    // now see what it really is, rather than what we were told it was.
+    let media_type = {
+        let file_path = file_path.clone();
+        tokio::task::spawn_blocking(move || identify(&file_path)).await??
+    };
+    if media_type != file.media_type {
+        info!("Expected {}, but it's actually {}.", file.media_type, media_type);
+    }
+
     // return that sucker!
     Ok(Some(Media {
-        media_type: file.media_type,
+        media_type,
         file_path: TempFileHolder {
             dir: tempdir,
             path: file_path,
@@ -471,77 +996,76 @@ pub async fn download_media(file: UrlAndMediaType) -> crate::Result<Option<Media
     })) // ok cool we got a media, time to download it
 }
 
+/// stream `url` into a file at `path`, giving up as soon as it's bigger than `limits` allow.
+/// `url` should have been through `net::check` already. returns how many bytes we got.
+async fn download_to(url: Url, path: &Path, limits: DownloadLimits) -> crate::Result<u64> {
+    let mut resp = crate::net::client()
+        .get(url)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|err| ArtificeError::DownloadFailed(err.to_string()))?;
+
+    // if they tell us up front, we don't have to download anything to know
+    if let Some(length) = resp.content_length() {
+        limits.check_size(length)?;
+    }
+
+    // but they might be lying, so count as we go.
+    let mut out = File::create(path)?;
+    let mut written: u64 = 0;
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|err| ArtificeError::DownloadFailed(err.to_string()))?
+    {
+        written += chunk.len() as u64;
+        // the half-written file goes away with its temp folder
+        limits.check_size(written)?;
+        out.write_all(&chunk)?;
+    }
+    Ok(written)
+}
+
 // rotate media
-#[derive(Debug, poise::ChoiceParameter, PartialEq, Eq, Clone, Copy)]
+// serialized with the same names as the choices
+#[derive(
+    Debug, poise::ChoiceParameter, PartialEq, Eq, Clone, Copy, serde::Serialize, serde::Deserialize,
+)]
 pub enum Rotation {
     #[name = "90"]
+    #[serde(rename = "90")]
     Cw = 1,
     #[name = "90ccw"]
+    #[serde(rename = "90ccw")]
     Ccw = 0,
     #[name = "180"]
+    #[serde(rename = "180")]
     Half = 2,
     #[name = "vflip"]
+    #[serde(rename = "vflip")]
     FlipV = 3,
     #[name = "hflip"]
+    #[serde(rename = "hflip")]
     FlipH = 4,
 }
 
 impl Rotation {
-    pub fn to_command(self) -> &'static str {
+    pub fn to_filters(self) -> Vec<Filter> {
         match self {
-            Rotation::Cw => "transpose=1",
-            Rotation::Ccw => "transpose=0",
-            Rotation::Half => "hflip, vflip",
-            Rotation::FlipV => "vflip",
-            Rotation::FlipH => "hflip",
+            Rotation::Cw => vec![Filter::new("transpose").arg(1)],
+            Rotation::Ccw => vec![Filter::new("transpose").arg(0)],
+            Rotation::Half => vec![Filter::new("hflip"), Filter::new("vflip")],
+            Rotation::FlipV => vec![Filter::new("vflip")],
+            Rotation::FlipH => vec![Filter::new("hflip")],
         }
     }
 }
 
-pub async fn rotate_and_flip(input: Media, rotation: Rotation) -> Result<Media, crate::Error> {
-    // let's rotate some stuff!
-    if input.media_type == MediaType::Audio {
-        // Cant resize audio.
-        return Err("Cannot rotate a audio file.".into());
-    }
-
-    // good to go!
-
-    // get the extension of the input file
-    let extension = input.file_path.path.extension().unwrap();
-
-    // create a tempfile to store the output.
-    let dir = new_temp_media(extension);
-
-    // now rotate the media using filters!
-    let output = FfmpegCommand::new()
-        .hwaccel(std::env::var("HW_ACCEL").unwrap_or("none".to_string()))
-        .input(input.file_path.path.as_path().to_str().unwrap()) // input file
-        .args([
-            // set the dimensions
-            "-vf",
-            rotation.to_command(),
-        ])
-        .codec_audio("copy") // copy audio codec
-        .output(dir.path.to_str().unwrap())
-        .spawn()
-        .unwrap(); // run that sucker
-
-    // wait for that to finish
-    ffbabysit(output)?;
-
-    Ok(Media {
-        media_type: input.media_type,
-        file_path: input.file_path,
-        output_tempfile: Some(TempFileHolder {
-            dir: dir.dir, // durrrrr
-            path: dir.path,
-        }),
-    })
-}
-
 #[test]
 fn resize_test() {
+    use crate::operations::MediaOperation;
+
     ffmpeg_sidecar::download::auto_download().unwrap();
     //get current path to src
     let srcpath = env!("CARGO_MANIFEST_DIR");
@@ -584,7 +1108,12 @@ fn resize_test() {
     for i in testfiles {
         let m_type = i.media_type;
         println!("Running {}", i.file_path.path.display());
-        let resize_result = resize_media(i, 128, 128);
+        let info = crate::probe::probe(&i.file_path.path).unwrap();
+        let resize = crate::operations::Resize {
+            width: 128,
+            height: 128,
+        };
+        let resize_result = resize.execute(i, &info, &JobControl::default());
         match resize_result {
             Ok(okay) => {
                 println!(
@@ -604,6 +1133,8 @@ fn resize_test() {
 
 #[test]
 fn auto_resize_test() {
+    use crate::operations::MediaOperation;
+
     ffmpeg_sidecar::download::auto_download().unwrap();
     //get current path to src
     let srcpath = env!("CARGO_MANIFEST_DIR");
@@ -643,7 +1174,12 @@ fn auto_resize_test() {
     for i in testfiles {
         let m_type = i.media_type;
         println!("Running {}", i.file_path.path.display());
-        let resize_result = resize_media(i, 167, 0);
+        let info = crate::probe::probe(&i.file_path.path).unwrap();
+        let resize = crate::operations::Resize {
+            width: 167,
+            height: 0,
+        };
+        let resize_result = resize.execute(i, &info, &JobControl::default());
         match resize_result {
             Ok(okay) => {
                 println!(
@@ -660,3 +1196,118 @@ fn auto_resize_test() {
         }
     }
 }
+
+#[test]
+fn resize_filters_test() {
+    // odd sizes get evened out
+    assert_eq!(
+        resize_filters(101, 255),
+        vec![Filter::new("scale").arg(102).arg(256)]
+    );
+    // a width of 0 keeps the aspect ratio, but can't go past the size limit
+    assert_eq!(
+        resize_filters(0, 256),
+        vec![Filter::new("scale")
+            .arg("max(2,min(8000,2*trunc(iw*256/ih/2)))")
+            .arg(256)]
+    );
+}
+
+#[tokio::test]
+async fn download_limit_test() {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // a server that hands out `body` once per connection, with or without saying how big it is
+    let serve = |body: Vec<u8>, with_length: bool| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // straight to download_to, so it doesn't get turned away for being local
+        let url = format!("http://{}/file.bin", listener.local_addr().unwrap());
+        let url = Url::parse(&url).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let length = if with_length {
+                format!("Content-Length: {}\r\n", body.len())
+            } else {
+                String::new()
+            };
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\n{}Connection: close\r\n\r\n", length);
+            let _ = stream.write_all(&body);
+        });
+        url
+    };
+    let limits = DownloadLimits {
+        max_bytes: 1024,
+        timeout: Duration::from_secs(10),
+    };
+    let dir = new_temp_dir();
+    let path = dir.path().join("file.bin");
+
+    let small = serve(vec![0; 512], true);
+    assert_eq!(download_to(small, &path, limits).await.unwrap(), 512);
+
+    // turned away from the header alone
+    let big = serve(vec![0; 4096], true);
+    let err = download_to(big, &path, limits).await.unwrap_err();
+    assert!(matches!(err, ArtificeError::TooLarge(_)));
+
+    // and caught while streaming when it doesn't say
+    let sneaky = serve(vec![0; 4096], false);
+    let err = download_to(sneaky, &path, limits).await.unwrap_err();
+    assert!(matches!(err, ArtificeError::TooLarge(_)));
+}
+
+#[test]
+fn url_test() {
+    assert_eq!(
+        urls_in("look at this https://example.com/cat.png. and <https://example.com/a.mp4>!"),
+        vec!["https://example.com/cat.png", "https://example.com/a.mp4"]
+    );
+    assert!(urls_in("no links here").is_empty());
+
+    assert!("https://example.com/cat.png".parse::<MediaUrl>().is_ok());
+    assert!("<https://example.com/cat.png>".parse::<MediaUrl>().is_ok());
+    // has to be a real link, or prefix commands lose their next argument
+    assert!("resize".parse::<MediaUrl>().is_err());
+    assert!("ftp://example.com/cat.png".parse::<MediaUrl>().is_err());
+}
+
+#[test]
+fn emoji_test() {
+    assert_eq!(
+        emojis_in("lol <:kek:123456> <a:dance:789> :not_custom:"),
+        vec![
+            CustomEmoji {
+                id: 123456,
+                animated: false
+            },
+            CustomEmoji {
+                id: 789,
+                animated: true
+            },
+        ]
+    );
+    let dance: CustomEmoji = "<a:dance:789>".parse().unwrap();
+    assert_eq!(dance.media().media_type, MediaType::Gif);
+    assert!(dance.media().url.ends_with("/789.gif"));
+    assert!("resize".parse::<CustomEmoji>().is_err());
+    // only a message made of emoji counts as media on its own
+    assert_eq!(emoji_only(" <:kek:123456> <a:dance:789>\n").len(), 2);
+    assert!(emoji_only("@artifice caption lol <:kek:123456>").is_empty());
+    assert!(emoji_only("").is_empty());
+}
+
+#[test]
+fn cdn_image_test() {
+    let still = cdn_image("avatars/1234", "abcdef");
+    assert_eq!(still.media_type, MediaType::Image);
+    assert_eq!(
+        still.url,
+        "https://cdn.discordapp.com/avatars/1234/abcdef.png?size=1024"
+    );
+    let animated = cdn_image("icons/5678", "a_abcdef");
+    assert_eq!(animated.media_type, MediaType::Gif);
+    assert!(animated.url.contains("a_abcdef.gif"));
+}
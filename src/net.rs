@@ -0,0 +1,166 @@
+// talking to other servers on someone else's say-so.
+//
+// links come from whoever's in the chat, so nothing we fetch gets to point back inside:
+// loopback, private ranges, cloud metadata endpoints, or our own http api.
+// the resolver drops those addresses, so redirects and sneaky dns get caught too.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+
+use crate::ArtificeError;
+
+/// most redirects we'll follow before giving up
+const MAX_REDIRECTS: usize = 5;
+
+/// the client for everything outside discord, shared so connections get reused
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .dns_resolver(Arc::new(PublicOnly))
+            .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if check_url(attempt.url()).is_err() {
+                    attempt.error("redirected somewhere we aren't allowed to go")
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .expect("couldn't build the http client")
+    })
+}
+
+/// turn away links we shouldn't be fetching before we even try.
+/// hostnames get checked again when they're resolved.
+pub fn check(url: &str) -> crate::Result<Url> {
+    let url = Url::parse(url).map_err(|_| ArtificeError::Invalid("That isn't a link.".into()))?;
+    check_url(&url)?;
+    Ok(url)
+}
+
+fn check_url(url: &Url) -> crate::Result {
+    let forbidden = || ArtificeError::Invalid("That link points somewhere we can't go.".into());
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(forbidden());
+    }
+    let Some(host) = url.host_str() else {
+        return Err(forbidden());
+    };
+    // ipv6 hosts keep their brackets
+    let allowed = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public(ip),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(forbidden())
+    }
+}
+
+/// whether `ip` is out on the internet, and not one of ours
+pub fn is_public(ip: IpAddr) -> bool {
+    let outside = match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    };
+    outside && Some(ip) != own_api()
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        // 169.254.169.254 and friends live here
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || first == 0
+        // carrier-grade nat
+        || (first == 100 && second & 0xc0 == 64)
+        || first >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || first & 0xfe00 == 0xfc00
+        // link local
+        || first & 0xffc0 == 0xfe80)
+}
+
+/// where our own http api is, if it's on an address that isn't already off limits
+fn own_api() -> Option<IpAddr> {
+    static OWN: OnceLock<Option<IpAddr>> = OnceLock::new();
+    *OWN.get_or_init(|| {
+        std::env::var("HTTP_BIND")
+            .ok()?
+            .parse::<SocketAddr>()
+            .ok()
+            .map(|bind| bind.ip())
+            .filter(|ip| !ip.is_unspecified())
+    })
+}
+
+/// a resolver that forgets about every address we aren't allowed to go to
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} isn't somewhere we can go", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[test]
+fn public_ip_test() {
+    let public = |ip: &str| is_public(ip.parse().unwrap());
+    assert!(public("1.1.1.1"));
+    assert!(public("2606:4700:4700::1111"));
+    assert!(!public("127.0.0.1"));
+    assert!(!public("10.1.2.3"));
+    assert!(!public("192.168.0.1"));
+    assert!(!public("169.254.169.254"));
+    assert!(!public("100.64.0.1"));
+    assert!(!public("0.0.0.0"));
+    assert!(!public("::1"));
+    assert!(!public("fd00::1"));
+    assert!(!public("fe80::1"));
+    assert!(!public("::ffff:127.0.0.1"));
+}
+
+#[test]
+fn check_test() {
+    assert!(check("https://media.tenor.com/abc/cat.gif").is_ok());
+    assert!(check("http://1.1.1.1/cat.png").is_ok());
+    assert!(check("http://127.0.0.1:8080/jobs").is_err());
+    assert!(check("http://[::1]/").is_err());
+    assert!(check("http://169.254.169.254/latest/meta-data/").is_err());
+    assert!(check("http://localhost/").is_err());
+    assert!(check("file:///etc/passwd").is_err());
+    assert!(check("not a link").is_err());
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use super::MediaOperation;
+use crate::captions::caption_media;
+use crate::ffmpeg_babysitter::JobControl;
+use crate::media_helpers::Media;
+use crate::probe::MediaInfo;
+
+/// black text on a white bar, above or below the media
+pub struct Caption {
+    pub text: String,
+    pub bottom: bool,
+}
+
+impl MediaOperation for Caption {
+    fn name(&self) -> &'static str {
+        "caption"
+    }
+
+    fn timeout(&self) -> Duration {
+        // stacking two inputs is slower than a plain filter
+        Duration::from_secs(180)
+    }
+
+    fn execute(
+        &self,
+        input: Media,
+        info: &MediaInfo,
+        control: &JobControl,
+    ) -> crate::Result<Media> {
+        caption_media(
+            self.text.clone(),
+            input,
+            info,
+            self.bottom,
+            (0, 0, 0),
+            (255, 255, 255),
+            control,
+        )
+    }
+}
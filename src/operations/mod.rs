@@ -0,0 +1,92 @@
+// every effect artifice can apply lives in here.
+// to add a new one: make a struct that implements `MediaOperation`,
+// give it a `JobType` variant, and hook it up in `JobType::operation`.
+
+mod caption;
+mod resize;
+mod rotate;
+
+pub use caption::Caption;
+pub use resize::Resize;
+pub use rotate::Rotate;
+
+use std::time::Duration;
+
+use crate::ffmpeg_babysitter::JobControl;
+use crate::filter_graph::Filter;
+use crate::media_helpers::{filter_media, Media, MediaType};
+use crate::probe::MediaInfo;
+
+/// how long an operation gets before ffmpeg is killed, unless it says otherwise
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// everything that isn't just sound
+pub const VISUAL_MEDIA: &[MediaType] = &[MediaType::Video, MediaType::Gif, MediaType::Image];
+
+/// something we can do to a piece of media
+pub trait MediaOperation: Send + Sync {
+    /// short name for logs and error messages, ie "resize"
+    fn name(&self) -> &'static str;
+
+    /// which kinds of media this operation can take in
+    fn supported_inputs(&self) -> &'static [MediaType] {
+        VISUAL_MEDIA
+    }
+
+    /// what kind of media comes out when given `input`
+    fn output_type(&self, input: MediaType) -> MediaType {
+        input
+    }
+
+    /// make sure we can actually work on `input` before doing anything expensive
+    fn validate(&self, input: MediaType) -> crate::Result {
+        if self.supported_inputs().contains(&input) {
+            Ok(())
+        } else {
+            Err(crate::ArtificeError::Unsupported(format!(
+                "Cannot {} {} files.",
+                self.name(),
+                input
+            )))
+        }
+    }
+
+    /// the video filters this operation boils down to, if that's all it is.
+    /// runs of operations that return `Some` here get squashed into a single
+    /// ffmpeg pass instead of re-encoding the media once per step.
+    fn filters(&self) -> Option<Vec<Filter>> {
+        None
+    }
+
+    /// how long this is allowed to run before we give up on it.
+    /// `FFMPEG_TIMEOUT_<NAME>` in the environment overrides this, see `timeout_for`.
+    fn timeout(&self) -> Duration {
+        DEFAULT_TIMEOUT
+    }
+
+    /// do the thing! `input` has already been through `validate`, and `info` is what `probe` found in it.
+    /// this blocks while ffmpeg runs, so keep it off the async runtime.
+    /// `control` gets handed to the babysitter so the job can be stopped part way.
+    /// operations that are nothing but `filters` get those run as is, so they can skip this.
+    fn execute(
+        &self,
+        input: Media,
+        _info: &MediaInfo,
+        control: &JobControl,
+    ) -> crate::Result<Media> {
+        let filters = self.filters().ok_or_else(|| {
+            crate::ArtificeError::Internal(format!("{} has nothing to run.", self.name()))
+        })?;
+        filter_media(input, filters, control)
+    }
+}
+
+/// how long `operation` gets to run, with `FFMPEG_TIMEOUT_<NAME>` (in seconds) taking priority,
+/// ie `FFMPEG_TIMEOUT_CAPTION=300`
+pub fn timeout_for(operation: &dyn MediaOperation) -> Duration {
+    std::env::var(format!("FFMPEG_TIMEOUT_{}", operation.name().to_uppercase()))
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_else(|| operation.timeout())
+}
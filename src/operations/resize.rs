@@ -0,0 +1,19 @@
+use super::MediaOperation;
+use crate::filter_graph::Filter;
+use crate::media_helpers::resize_filters;
+
+/// resize to a set size, a width of 0 keeps the aspect ratio
+pub struct Resize {
+    pub width: u16,
+    pub height: u16,
+}
+
+impl MediaOperation for Resize {
+    fn name(&self) -> &'static str {
+        "resize"
+    }
+
+    fn filters(&self) -> Option<Vec<Filter>> {
+        Some(resize_filters(self.width, self.height))
+    }
+}
@@ -0,0 +1,18 @@
+use super::MediaOperation;
+use crate::filter_graph::Filter;
+use crate::media_helpers::Rotation;
+
+/// rotate or flip
+pub struct Rotate {
+    pub rotation: Rotation,
+}
+
+impl MediaOperation for Rotate {
+    fn name(&self) -> &'static str {
+        "rotate"
+    }
+
+    fn filters(&self) -> Option<Vec<Filter>> {
+        Some(self.rotation.to_filters())
+    }
+}
@@ -0,0 +1,175 @@
+// runs the operations of a job, feeding each output into the next step.
+//
+// steps that are nothing but video filters get saved up and run together,
+// so `resize | rotate | resize` is one ffmpeg pass instead of three re-encodes.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ffmpeg_babysitter::JobControl;
+use crate::filter_graph::Filter;
+use crate::job::{Job, JobPart};
+use crate::media_helpers::{filter_media, Media, MediaType};
+use crate::operations::{timeout_for, MediaOperation};
+use crate::probe::{probe, MediaInfo};
+use crate::queue::Turn;
+use crate::timings::Timings;
+
+/// a bunch of filter-only steps squashed together
+#[derive(Default)]
+struct FusedFilters {
+    filters: Vec<Filter>,
+    /// the longest any of the squashed steps was allowed on its own
+    timeout: Duration,
+    /// how many steps got squashed
+    steps: usize,
+}
+
+impl MediaOperation for FusedFilters {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn execute(
+        &self,
+        input: Media,
+        _info: &MediaInfo,
+        control: &JobControl,
+    ) -> crate::Result<Media> {
+        filter_media(input, self.filters.clone(), control)
+    }
+}
+
+/// make sure every step of `part` can handle what the step before it spits out,
+/// so we can turn the job away before it waits in the queue.
+pub fn validate_part(part: &JobPart, input: MediaType) -> crate::Result {
+    let mut media_type = input;
+    for job in &part.subparts {
+        let operation = job.operation();
+        operation.validate(media_type)?;
+        media_type = operation.output_type(media_type);
+    }
+    Ok(())
+}
+
+/// run every part of `job` on its matching media, one after another on the worker
+/// the queue gave `turn`, so a big batch can't jump ahead of everyone else's jobs.
+/// results come back in the same order as `media`.
+/// how long each part took goes into `timings`.
+pub async fn run_job(
+    job: Job,
+    media: Vec<Media>,
+    turn: Turn,
+    control: JobControl,
+    timings: Arc<Timings>,
+) -> crate::Result<Vec<Media>> {
+    // the worker stays ours until the last part is done
+    let _turn = turn;
+    control.track_parts(job.parts.len());
+    let mut results = Vec::with_capacity(media.len());
+    for (index, (part, media)) in job.parts.into_iter().zip(media).enumerate() {
+        let control = control.for_part(index);
+        let media_type = media.media_type;
+        let size = std::fs::metadata(&media.file_path.path).ok().map(|m| m.len());
+        let started = std::time::Instant::now();
+        let result: Media = run_part(&part, media, &control).await?;
+        control.finish_part();
+        timings.record(&part, media_type, size, started.elapsed());
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// run every subpart of `part` in order, only the final output is kept.
+pub async fn run_part(part: &JobPart, media: Media, control: &JobControl) -> crate::Result<Media> {
+    let mut media = media;
+    let mut media_type = media.media_type;
+    // filter-only steps waiting to go through ffmpeg together
+    let mut pending = FusedFilters::default();
+    // how many steps have gone through ffmpeg, for progress reports
+    let total = part.subparts.len();
+    let mut finished = 0;
+
+    for (step, job) in part.subparts.iter().enumerate() {
+        let operation = job.operation();
+        operation.validate(media_type)?;
+        media_type = operation.output_type(media_type);
+        tracing::info!("Running step {} of {}: {:?}", step + 1, part.subparts.len(), job);
+
+        if let Some(filters) = operation.filters() {
+            // save it for later
+            pending.filters.extend(filters);
+            pending.timeout = pending.timeout.max(timeout_for(&*operation));
+            pending.steps += 1;
+            continue;
+        }
+
+        // this step has to run on its own, so finish off the filters before it first
+        let fused = pending.steps;
+        media = flush_filters(
+            media,
+            &mut pending,
+            &control.at_steps(finished, fused, total),
+        )
+        .await?;
+        finished += fused;
+        media = execute(media, operation, &control.at_steps(finished, 1, total)).await?;
+        finished += 1;
+        media.media_type = media_type;
+    }
+
+    // anything left over
+    let fused = pending.steps;
+    let mut media = flush_filters(
+        media,
+        &mut pending,
+        &control.at_steps(finished, fused, total),
+    )
+    .await?;
+    media.media_type = media_type;
+    Ok(media)
+}
+
+/// run all the `pending` filters in one go, if there are any
+async fn flush_filters(
+    media: Media,
+    pending: &mut FusedFilters,
+    control: &JobControl,
+) -> crate::Result<Media> {
+    if pending.filters.is_empty() {
+        return Ok(media);
+    }
+    tracing::info!("Running {} fused filter(s)", pending.filters.len());
+    let fused = std::mem::take(pending);
+    execute(media, Box::new(fused), control).await
+}
+
+/// run a single operation, using the output of the last step as the input
+async fn execute(
+    media: Media,
+    operation: Box<dyn MediaOperation>,
+    control: &JobControl,
+) -> crate::Result<Media> {
+    // no point starting something nobody wants anymore
+    control.check()?;
+    // the clock starts now, waiting on earlier steps doesn't count against it
+    let control = control.with_timeout(timeout_for(&*operation));
+    let media = if media.output_tempfile.is_some() {
+        // the last output is our new input
+        media.into_next_input()?
+    } else {
+        media
+    };
+    // operations block on ffmpeg, don't hold up the rest of the bot
+    tokio::task::spawn_blocking(move || {
+        let info = probe(&media.file_path.path)?;
+        // so ffmpeg's timestamps can be turned into a percentage
+        let control = control.with_duration(info.duration);
+        operation.execute(media, &info, &control)
+    })
+    .await?
+}
@@ -0,0 +1,363 @@
+// finding out what's actually inside a media file, by asking ffprobe.
+//
+// files in the wild are weird: mp3s with cover art, videos whose first stream is audio,
+// phone videos that are secretly sideways. everything that needs to know about a file
+// should go through `probe` instead of poking at ffprobe output itself.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::ffmpeg_babysitter::run_limited;
+use crate::ArtificeError;
+
+/// how long ffprobe gets to look at a file, it only has to read the headers
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// everything we care about in a media file
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaInfo {
+    /// the stream that gets worked on, if there's anything to look at
+    pub video: Option<VideoStream>,
+    /// the main audio stream, if there's anything to hear
+    pub audio: Option<AudioStream>,
+    /// how long it plays for. still images don't have one
+    pub duration: Option<Duration>,
+    /// bits per second across the whole file
+    pub bitrate: Option<u64>,
+    /// the container, ie "mov,mp4,m4a,3gp,3g2,mj2" or "png_pipe"
+    pub format: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoStream {
+    pub index: usize,
+    pub codec: String,
+    /// as stored in the file, see `MediaInfo::dimensions` for how it's shown
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: Option<f64>,
+    pub frame_count: Option<u64>,
+    pub has_alpha: bool,
+    /// how far it should be turned clockwise when shown, 0, 90, 180 or 270
+    pub rotation: u32,
+    /// album art and the like, a picture riding along with some audio
+    pub is_cover_art: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStream {
+    pub index: usize,
+    pub codec: String,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+impl MediaInfo {
+    /// (width, height) the way it looks when played, after any rotation.
+    /// ffmpeg rotates while decoding, so this is also what filters get to work with.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let video = self.video.as_ref()?;
+        if video.rotation % 180 == 90 {
+            Some((video.height, video.width))
+        } else {
+            Some((video.width, video.height))
+        }
+    }
+
+    /// the video stream, or an error saying there isn't one worth working on
+    pub fn require_video(&self) -> crate::Result<&VideoStream> {
+        match &self.video {
+            Some(video) if !video.is_cover_art => Ok(video),
+            _ => Err(ArtificeError::Unsupported(
+                "That file doesn't have any video in it.".into(),
+            )),
+        }
+    }
+
+    /// more than one frame to look at
+    pub fn is_animated(&self) -> bool {
+        match &self.video {
+            Some(video) if !video.is_cover_art => video.frame_count.map_or(
+                // images don't say how many frames they have, but they don't last either
+                self.duration.is_some_and(|duration| !duration.is_zero()),
+                |frames| frames > 1,
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// ask ffprobe about `path`
+pub fn probe(path: &Path) -> crate::Result<MediaInfo> {
+    let mut command = Command::new(ffmpeg_sidecar::ffprobe::ffprobe_path());
+    command
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(path);
+    // same limits as ffmpeg, a nasty file can hang ffprobe just as well
+    let output = run_limited(&mut command, PROBE_TIMEOUT)?;
+    if !output.status.success() {
+        tracing::info!(
+            "ffprobe couldn't read {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Err(ArtificeError::Unsupported(
+            "Couldn't make sense of that file.".into(),
+        ));
+    }
+    parse(&output.stdout)
+}
+
+/// turn ffprobe's json into a `MediaInfo`
+fn parse(json: &[u8]) -> crate::Result<MediaInfo> {
+    let raw: RawProbe = serde_json::from_slice(json)?;
+    let format = raw.format.unwrap_or_default();
+
+    // a real video stream beats a cover image, and the default stream beats the rest
+    let video = raw
+        .streams
+        .iter()
+        .filter(|stream| stream.codec_type.as_deref() == Some("video"))
+        .filter(|stream| stream.width.is_some() && stream.height.is_some())
+        .min_by_key(|stream| {
+            (
+                stream.disposition.attached_pic != 0,
+                stream.disposition.default == 0,
+            )
+        })
+        .map(video_stream);
+    let audio = raw
+        .streams
+        .iter()
+        .filter(|stream| stream.codec_type.as_deref() == Some("audio"))
+        .min_by_key(|stream| stream.disposition.default == 0)
+        .map(|stream| AudioStream {
+            index: stream.index,
+            codec: stream.codec_name.clone().unwrap_or_default(),
+            sample_rate: stream
+                .sample_rate
+                .as_deref()
+                .and_then(|rate| rate.parse().ok()),
+            channels: stream.channels,
+        });
+
+    let duration = format
+        .duration
+        .as_deref()
+        .and_then(parse_seconds)
+        .filter(|duration| !duration.is_zero());
+    Ok(MediaInfo {
+        video: video.map(|mut video| {
+            // containers know how long they are better than streams know how many frames they have
+            if video.frame_count.is_none() && !video.is_cover_art {
+                video.frame_count = duration
+                    .zip(video.frame_rate)
+                    .map(|(duration, rate)| (duration.as_secs_f64() * rate).round() as u64);
+            }
+            video
+        }),
+        audio,
+        duration,
+        bitrate: format
+            .bit_rate
+            .as_deref()
+            .and_then(|rate| rate.parse().ok()),
+        format: format.format_name.unwrap_or_default(),
+    })
+}
+
+fn video_stream(stream: &RawStream) -> VideoStream {
+    let pix_fmt = stream.pix_fmt.as_deref().unwrap_or_default();
+    VideoStream {
+        index: stream.index,
+        codec: stream.codec_name.clone().unwrap_or_default(),
+        width: stream.width.unwrap_or_default(),
+        height: stream.height.unwrap_or_default(),
+        frame_rate: [&stream.avg_frame_rate, &stream.r_frame_rate]
+            .into_iter()
+            .find_map(|rate| rate.as_deref().and_then(parse_rate)),
+        frame_count: stream
+            .nb_frames
+            .as_deref()
+            .and_then(|frames| frames.parse().ok()),
+        has_alpha: has_alpha(pix_fmt),
+        rotation: rotation(stream),
+        is_cover_art: stream.disposition.attached_pic != 0,
+    }
+}
+
+/// "30000/1001" into 29.97, nothing for ffprobe's "0/0"
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/')?;
+    let (numerator, denominator): (f64, f64) = (numerator.parse().ok()?, denominator.parse().ok()?);
+    let rate = numerator / denominator;
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+fn parse_seconds(seconds: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds.parse().ok()?).ok()
+}
+
+/// pixel formats with an alpha channel, gifs come out as bgra
+fn has_alpha(pix_fmt: &str) -> bool {
+    [
+        "yuva", "rgba", "bgra", "argb", "abgr", "gbrap", "ya8", "ya16", "pal8",
+    ]
+    .iter()
+    .any(|alpha| pix_fmt.starts_with(alpha))
+}
+
+/// clockwise degrees, from either the old `rotate` tag or the newer display matrix
+fn rotation(stream: &RawStream) -> u32 {
+    let from_tag = stream.tags.get("rotate").and_then(value_as_f64);
+    // the display matrix says how to turn it back, so it's the other way around
+    let from_side_data = stream
+        .side_data_list
+        .iter()
+        .find_map(|side_data| side_data.get("rotation").and_then(value_as_f64))
+        .map(|rotation| -rotation);
+    let degrees = from_tag.or(from_side_data).unwrap_or_default().round() as i64;
+    // snap to quarter turns, nothing else comes up in practice
+    ((degrees.rem_euclid(360) + 45) / 90 % 4 * 90) as u32
+}
+
+/// ffprobe isn't consistent about numbers being strings
+fn value_as_f64(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+// only the bits of ffprobe's output we actually look at
+
+#[derive(Deserialize)]
+struct RawProbe {
+    #[serde(default)]
+    streams: Vec<RawStream>,
+    format: Option<RawFormat>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawStream {
+    index: usize,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    pix_fmt: Option<String>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    nb_frames: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    disposition: RawDisposition,
+    tags: HashMap<String, serde_json::Value>,
+    side_data_list: Vec<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawDisposition {
+    default: u8,
+    attached_pic: u8,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawFormat {
+    format_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[test]
+fn probe_parse_test() {
+    // an mp3 with album art: the "video" is just a picture
+    let mp3 = br#"{
+        "streams": [
+            {"index": 0, "codec_type": "audio", "codec_name": "mp3", "sample_rate": "44100", "channels": 2,
+             "disposition": {"default": 0, "attached_pic": 0}},
+            {"index": 1, "codec_type": "video", "codec_name": "mjpeg", "width": 500, "height": 500,
+             "pix_fmt": "yuvj420p", "avg_frame_rate": "0/0", "r_frame_rate": "90000/1",
+             "disposition": {"default": 0, "attached_pic": 1}}
+        ],
+        "format": {"format_name": "mp3", "duration": "12.5", "bit_rate": "128000"}
+    }"#;
+    let info = parse(mp3).unwrap();
+    assert!(info.video.as_ref().unwrap().is_cover_art);
+    assert!(info.require_video().is_err());
+    assert!(!info.is_animated());
+    assert_eq!(info.audio.as_ref().unwrap().sample_rate, Some(44100));
+    assert_eq!(info.duration, Some(Duration::from_millis(12_500)));
+
+    // a sideways phone video whose first stream is the audio
+    let phone = br#"{
+        "streams": [
+            {"index": 0, "codec_type": "audio", "codec_name": "aac"},
+            {"index": 1, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+             "pix_fmt": "yuv420p", "avg_frame_rate": "30000/1001", "nb_frames": "300",
+             "side_data_list": [{"side_data_type": "Display Matrix", "rotation": -90}]}
+        ],
+        "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.010000"}
+    }"#;
+    let info = parse(phone).unwrap();
+    let video = info.require_video().unwrap();
+    assert_eq!(video.index, 1);
+    assert_eq!(video.rotation, 90);
+    assert_eq!(info.dimensions(), Some((1080, 1920)));
+    assert_eq!(video.frame_count, Some(300));
+    assert!((video.frame_rate.unwrap() - 29.97).abs() < 0.01);
+    assert!(info.is_animated());
+
+    // a png with transparency
+    let png = br#"{
+        "streams": [{"index": 0, "codec_type": "video", "codec_name": "png", "width": 64, "height": 32,
+                     "pix_fmt": "rgba", "avg_frame_rate": "0/0", "r_frame_rate": "25/1"}],
+        "format": {"format_name": "png_pipe"}
+    }"#;
+    let info = parse(png).unwrap();
+    assert!(info.require_video().unwrap().has_alpha);
+    assert_eq!(info.dimensions(), Some((64, 32)));
+    assert_eq!(info.duration, None);
+    assert!(!info.is_animated());
+}
+
+#[test]
+fn probe_file_test() {
+    ffmpeg_sidecar::download::auto_download().unwrap();
+    let srcpath = env!("CARGO_MANIFEST_DIR");
+    let video = probe(Path::new(&format!(
+        "{}/src/test_files/text-video-test.mp4",
+        srcpath
+    )))
+    .unwrap();
+    assert!(video.is_animated());
+    let png = probe(Path::new(&format!(
+        "{}/src/test_files/bajacat.png",
+        srcpath
+    )))
+    .unwrap();
+    assert!(!png.is_animated());
+    assert!(png.dimensions().is_some());
+    let mp3 = probe(Path::new(&format!(
+        "{}/src/test_files/CC0-jazz-guitar.mp3",
+        srcpath
+    )))
+    .unwrap();
+    assert!(mp3.audio.is_some());
+    assert!(mp3.require_video().is_err());
+}
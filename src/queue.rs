@@ -1,66 +1,455 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, Weak},
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
 };
 
-use poise::{CreateReply, ReplyHandle};
+use poise::serenity_prelude::UserId;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::ffmpeg_babysitter::JobControl;
+use crate::timings::Timings;
+use crate::ArtificeError;
 
 type JobHandle = crate::JobId;
 
-#[derive(Default)]
+/// most jobs one person can have going at once, queued or running
+pub const MAX_JOBS_PER_USER: usize = 3;
+
+/// who asked for a job, everyone gets their fair share of turns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Requester {
+    Discord(UserId),
+    /// someone using the http api, going by where they're connecting from
+    Api(IpAddr),
+}
+
+impl From<UserId> for Requester {
+    fn from(user: UserId) -> Self {
+        Requester::Discord(user)
+    }
+}
+
+/// jobs with a higher priority skip ahead of everything below them
+#[derive(
+    Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, serde::Serialize, serde::Deserialize,
+)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// owners and configured roles
+    High,
+}
+
+/// someone waiting in line, or already being served
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    job: JobHandle,
+    requester: Option<Requester>,
+    priority: Priority,
+    /// got a worker, it only stays in line so it still counts as one of their turns
+    started: bool,
+}
+
+/// who asked for a job and how to stop it
+struct Registration {
+    /// None for jobs nobody in particular asked for
+    requester: Option<Requester>,
+    control: JobControl,
+    /// how long we think it'll take once it's running
+    estimate: Option<Duration>,
+    /// when it got its turn, None while it's still waiting
+    started: Option<std::time::Instant>,
+}
+
+type Registrations = std::sync::Mutex<HashMap<JobHandle, Registration>>;
+
+/// don't tell people about their position more often than this, discord doesn't like it
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// how often to check whether everything finished while shutting down
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// how long cancelled jobs get to clean up after the drain times out
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// the line itself, in the order people showed up, including jobs that are running.
+/// use `Line::order` for the order the rest actually get served in.
+struct Line {
+    jobs: tokio::sync::RwLock<VecDeque<Entry>>,
+    /// poked every time the line changes, so waiters don't have to keep checking
+    changes: tokio::sync::watch::Sender<()>,
+    /// set once we're shutting down, nobody new gets in and nobody waiting gets a turn
+    closed: AtomicBool,
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Line {
+            jobs: Default::default(),
+            changes: tokio::sync::watch::channel(()).0,
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Line {
+    /// wake up everyone waiting, since their position might have changed
+    fn notify(&self) {
+        self.changes.send_replace(());
+    }
+
+    async fn remove(&self, job: JobHandle) {
+        self.jobs.write().await.retain(|other| other.job != job);
+        self.notify();
+    }
+
+    /// mark `job` as running, it's not waiting anymore but it still took a turn
+    async fn start(&self, job: JobHandle) {
+        for entry in self.jobs.write().await.iter_mut() {
+            if entry.job == job {
+                entry.started = true;
+            }
+        }
+        self.notify();
+    }
+
+    /// the order waiting jobs get served in.
+    /// higher priorities go first, then everybody takes turns:
+    /// every user's first job comes before anyone's second, and so on,
+    /// and jobs that are already running count as a turn.
+    /// jobs nobody in particular asked for all share a single turn.
+    async fn order(&self) -> Vec<JobHandle> {
+        let lock = self.jobs.read().await;
+        let mut seen: HashMap<Option<Requester>, usize> = HashMap::new();
+        let mut ranked: Vec<(std::cmp::Reverse<Priority>, usize, JobHandle)> = lock
+            .iter()
+            .filter_map(|entry| {
+                let turn = seen.entry(entry.requester).or_default();
+                *turn += 1;
+                (!entry.started).then_some((std::cmp::Reverse(entry.priority), *turn, entry.job))
+            })
+            .collect();
+        // stable, so ties stay in the order they showed up
+        ranked.sort_by_key(|(priority, turn, _)| (*priority, *turn));
+        ranked.into_iter().map(|(_, _, job)| job).collect()
+    }
+}
+
+/// cloning gives another handle to the same queue
+#[derive(Clone)]
 pub struct JobQueue {
-    queue: std::sync::Arc<tokio::sync::RwLock<VecDeque<JobHandle>>>,
+    queue: Arc<Line>,
+    /// every job that can still be cancelled, queued or running
+    registrations: Arc<Registrations>,
+    /// how long things usually take, for guessing wait times
+    timings: Arc<Timings>,
+    /// one permit per worker, handed out in the order of the line
+    slots: Arc<Semaphore>,
+    /// how many jobs get worked on at once
+    workers: usize,
+}
+
+/// how a cancel request went
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CancelOutcome {
+    Cancelled,
+    NotYours,
+    NotFound,
+}
+
+/// a job the queue knows about. it can be cancelled until this is dropped,
+/// so hang on to it until the job is completely done, not just out of the queue.
+pub struct JobTicket {
+    pub job: JobHandle,
+    /// hand this to whatever runs the job
+    pub control: JobControl,
+    requester: Option<Requester>,
+    priority: Priority,
+    registrations: Weak<Registrations>,
+}
+
+impl Drop for JobTicket {
+    fn drop(&mut self) {
+        if let Some(registrations) = self.registrations.upgrade() {
+            if let Ok(mut lock) = registrations.lock() {
+                lock.remove(&self.job);
+            }
+        }
+    }
+}
+
+/// a job's turn to run. it keeps a worker busy and counts against
+/// its requester's turns until it's dropped.
+pub struct Turn {
+    /// the worker the queue gave us
+    pub permit: OwnedSemaphorePermit,
+    _guard: QueueGuard,
 }
 
 impl JobQueue {
-    /// add `job` to the queue, post updates about the position, and block till it's done
-    pub async fn wait<'ctx>(
+    /// a queue feeding `workers` jobs at a time
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        JobQueue {
+            queue: Arc::default(),
+            registrations: Arc::default(),
+            timings: Arc::default(),
+            slots: Arc::new(Semaphore::new(workers)),
+            workers,
+        }
+    }
+
+    pub fn timings(&self) -> Arc<Timings> {
+        self.timings.clone()
+    }
+
+    /// start keeping track of `job`, before it even gets in line.
+    /// fails if `requester` already has too many jobs going, unless they're high priority.
+    pub fn ticket(
         &self,
         job: JobHandle,
-        ctx: crate::Context<'ctx>,
-        message: &mut ReplyHandle<'ctx>,
-    ) -> crate::Result {
-        self.queue.write().await.push_back(job);
-        let _guard = QueueGuard {
+        requester: Option<Requester>,
+        priority: Priority,
+    ) -> crate::Result<JobTicket> {
+        if self.is_closed() {
+            return Err(ArtificeError::Restarting);
+        }
+        let control = JobControl::default();
+        {
+            let mut lock = self
+                .registrations
+                .lock()
+                .map_err(|_| ArtificeError::Internal("Job registrations are poisoned.".into()))?;
+            if requester.is_some() {
+                let running = lock
+                    .values()
+                    .filter(|registration| registration.requester == requester)
+                    .count();
+                if running >= MAX_JOBS_PER_USER && priority < Priority::High {
+                    return Err(ArtificeError::Invalid(format!(
+                        "You already have {} jobs going, wait for one to finish first.",
+                        running
+                    )));
+                }
+            }
+            lock.insert(
+                job,
+                Registration {
+                    requester,
+                    control: control.clone(),
+                    estimate: None,
+                    started: None,
+                },
+            );
+        }
+        Ok(JobTicket {
+            job,
+            control,
+            requester,
+            priority,
+            registrations: Arc::downgrade(&self.registrations),
+        })
+    }
+
+    /// let the queue know how long `ticket`'s job should take, so people behind it get an ETA
+    pub fn set_estimate(&self, ticket: &JobTicket, estimate: Option<Duration>) {
+        if let Ok(mut lock) = self.registrations.lock() {
+            if let Some(registration) = lock.get_mut(&ticket.job) {
+                registration.estimate = estimate;
+            }
+        }
+    }
+
+    /// add the job to the queue and block till it's its turn.
+    /// the job stays in line until it has a worker, so nobody gets one out of order.
+    /// `on_position` gets the position and a guess at the wait when the position changes,
+    /// at most once every `MIN_UPDATE_INTERVAL`, and always with 0 once it's the job's turn.
+    /// this is the same queue no matter where the job came from.
+    pub async fn wait_with<F, Fut>(
+        &self,
+        ticket: &JobTicket,
+        mut on_position: F,
+    ) -> crate::Result<Turn>
+    where
+        F: FnMut(usize, Option<Duration>) -> Fut,
+        Fut: std::future::Future<Output = crate::Result>,
+    {
+        let job = ticket.job;
+        // cancelled before we even got here?
+        ticket.control.check()?;
+        // listen before getting in line, so we can't miss anything
+        let mut changes = self.queue.changes.subscribe();
+        self.queue.jobs.write().await.push_back(Entry {
+            job,
+            requester: ticket.requester,
+            priority: ticket.priority,
+            started: false,
+        });
+        self.queue.notify();
+        let guard = QueueGuard {
             queue: Arc::downgrade(&self.queue),
             job,
         };
+
+        let mut last_reported: Option<usize> = None;
+        let mut next_update = tokio::time::Instant::now();
         loop {
-            if let Some(position) = self.poll(job).await {
-                message
-                    .edit(
-                        ctx,
-                        CreateReply::default().content(format!("Queue Position: {}", position)),
-                    )
-                    .await?;
-                if position == 0 {
-                    return Ok(());
+            if self.is_closed() {
+                return Err(ArtificeError::Restarting);
+            }
+            let Some(position) = self.position(job).await else {
+                // cancelling takes jobs out of the queue
+                ticket.control.check()?;
+                return Err(ArtificeError::Internal("Unexpectedly popped".into()));
+            };
+            if last_reported != Some(position) && tokio::time::Instant::now() >= next_update {
+                on_position(position, self.eta(job).await).await?;
+                last_reported = Some(position);
+                next_update = tokio::time::Instant::now() + MIN_UPDATE_INTERVAL;
+            }
+            // wait for the line to move or a worker to free up.
+            // only the front of the line asks for a worker, so they go out in order
+            tokio::select! {
+                changed = changes.changed() => changed?,
+                // too soon to tell them last time, see where we are now
+                _ = tokio::time::sleep_until(next_update), if last_reported != Some(position) => {}
+                Ok(permit) = self.slots.clone().acquire_owned(), if position == 1 => {
+                    // someone might have skipped ahead while we were waiting
+                    if self.is_closed() || self.position(job).await != Some(1) {
+                        continue;
+                    }
+                    self.start(job).await;
+                    on_position(0, Some(Duration::ZERO)).await?;
+                    return Ok(Turn {
+                        permit,
+                        _guard: guard,
+                    });
                 }
-            } else {
-                return Err("Unexpectedly popped".into());
             }
         }
     }
 
-    /// get position of `job`, or None if not in the queue
-    async fn poll(&self, job: JobHandle) -> Option<usize> {
-        let lock = self.queue.read().await;
-        for (i, other) in lock.iter().enumerate() {
-            if *other == job {
-                return Some(i);
+    /// `job` got its turn, remember when for guessing how long it has left
+    async fn start(&self, job: JobHandle) {
+        self.queue.start(job).await;
+        if let Ok(mut lock) = self.registrations.lock() {
+            if let Some(registration) = lock.get_mut(&job) {
+                registration.started = Some(std::time::Instant::now());
+            }
+        }
+    }
+
+    /// get position of `job`, 1 being next in line, or None if not in the queue
+    pub async fn position(&self, job: JobHandle) -> Option<usize> {
+        self.queue.order().await.iter().position(|other| *other == job).map(|ahead| ahead + 1)
+    }
+
+    /// roughly how long until `job` gets its turn, counting what's left of the running jobs.
+    /// None if it isn't queued, or we have no idea how long the jobs ahead of it take.
+    pub async fn eta(&self, job: JobHandle) -> Option<Duration> {
+        let mut ahead = self.queue.order().await;
+        let position = ahead.iter().position(|other| *other == job)?;
+        ahead.truncate(position);
+        let registrations = self.registrations.lock().ok()?;
+        let mut total = Duration::ZERO;
+        for registration in registrations.values() {
+            if let Some(started) = registration.started {
+                total += registration.estimate?.saturating_sub(started.elapsed());
+            }
+        }
+        for other in ahead {
+            total += registrations.get(&other)?.estimate?;
+        }
+        // the jobs ahead get split between the workers
+        Some(total / self.workers as u32)
+    }
+
+    /// stop `job`, whether it's still waiting or already running.
+    /// only whoever asked for it (or an owner) gets to do that.
+    pub async fn cancel(&self, job: JobHandle, by: UserId, is_owner: bool) -> CancelOutcome {
+        {
+            let Ok(lock) = self.registrations.lock() else {
+                return CancelOutcome::NotFound;
+            };
+            let Some(registration) = lock.get(&job) else {
+                return CancelOutcome::NotFound;
+            };
+            if registration.requester != Some(by.into()) && !is_owner {
+                return CancelOutcome::NotYours;
+            }
+            registration.control.cancel();
+        }
+        // don't make everyone behind it wait on a job that's never going to run
+        self.queue.remove(job).await;
+        tracing::info!("Job {} was cancelled by {}", *job, by);
+        CancelOutcome::Cancelled
+    }
+
+    /// the most recent job `user` asked for that hasn't finished yet
+    pub fn latest_job_of(&self, user: UserId) -> Option<JobHandle> {
+        let lock = self.registrations.lock().ok()?;
+        lock.iter()
+            .filter(|(_, registration)| registration.requester == Some(user.into()))
+            // discord ids go up over time
+            .map(|(job, _)| *job)
+            .max_by_key(|job| **job)
+    }
+
+    /// stop letting jobs in or out of the line, for shutting down.
+    /// everyone still waiting gets an error from `wait_with`, nobody else gets a worker.
+    pub fn close(&self) {
+        self.queue.closed.store(true, Ordering::SeqCst);
+        self.queue.notify();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.closed.load(Ordering::SeqCst)
+    }
+
+    /// after `close`, wait for every job still going to finish, for up to `timeout`.
+    /// whatever's left after that gets cancelled. returns how many that was.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.outstanding() > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+        }
+        let leftover = match self.registrations.lock() {
+            Ok(lock) => {
+                for registration in lock.values() {
+                    registration.control.cancel();
+                }
+                lock.len()
+            }
+            Err(_) => 0,
+        };
+        if leftover > 0 {
+            // give them a moment to kill ffmpeg and tell people what happened
+            let deadline = tokio::time::Instant::now() + CANCEL_GRACE;
+            while self.outstanding() > 0 && tokio::time::Instant::now() < deadline {
+                tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
             }
         }
-        None
+        leftover
+    }
+
+    /// jobs that haven't finished yet, queued or running
+    fn outstanding(&self) -> usize {
+        self.registrations.lock().map(|lock| lock.len()).unwrap_or(0)
     }
 
     pub async fn len(&self) -> usize {
-        self.queue.read().await.len()
+        self.queue.jobs.read().await.len()
     }
 }
 
 /// ensures that the job is removed when dropped
 struct QueueGuard {
-    queue: Weak<tokio::sync::RwLock<VecDeque<JobHandle>>>,
+    queue: Weak<Line>,
     job: JobHandle,
 }
 
@@ -69,9 +458,190 @@ impl Drop for QueueGuard {
         if let Some(queue) = self.queue.upgrade() {
             let job = self.job;
             tokio::spawn(async move {
-                let mut lock = queue.write().await;
-                lock.retain(|other| *other != job);
+                queue.remove(job).await;
             });
         }
     }
 }
+
+/// "~40s", "~3m" and so on
+pub fn format_wait(wait: Duration) -> String {
+    let secs = wait.as_secs();
+    match secs {
+        0..=59 => format!("~{}s", secs.max(1)),
+        60..=3599 => format!("~{}m", (secs + 30) / 60),
+        _ => format!("~{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
+#[tokio::test]
+async fn fair_order_test() {
+    let line = Line::default();
+    let alice = Some(Requester::Discord(UserId::new(1)));
+    let bob = Some(Requester::Discord(UserId::new(2)));
+    let entry = |job: u64, requester, priority| Entry {
+        job: crate::JobId(job),
+        requester,
+        priority,
+        started: false,
+    };
+    {
+        let mut jobs = line.jobs.write().await;
+        // alice spams, bob and the owner show up later
+        jobs.push_back(entry(1, alice, Priority::Normal));
+        jobs.push_back(entry(2, alice, Priority::Normal));
+        jobs.push_back(entry(3, alice, Priority::Normal));
+        jobs.push_back(entry(4, bob, Priority::Normal));
+        jobs.push_back(entry(5, bob, Priority::Normal));
+        jobs.push_back(entry(6, None, Priority::High));
+    }
+    let order: Vec<u64> = line.order().await.into_iter().map(|job| job.0).collect();
+    assert_eq!(order, [6, 1, 4, 2, 5, 3]);
+}
+
+#[test]
+fn cap_test() {
+    let queue = JobQueue::new(1);
+    let api = |ip: &str| Some(Requester::Api(ip.parse().unwrap()));
+    let tickets: Vec<JobTicket> = (0..MAX_JOBS_PER_USER as u64)
+        .map(|job| {
+            queue
+                .ticket(crate::JobId(job), api("10.0.0.1"), Priority::Normal)
+                .unwrap()
+        })
+        .collect();
+    // api clients get capped like everyone else
+    assert!(queue
+        .ticket(crate::JobId(10), api("10.0.0.1"), Priority::Normal)
+        .is_err());
+    // but only by their own jobs
+    assert!(queue
+        .ticket(crate::JobId(11), api("10.0.0.2"), Priority::Normal)
+        .is_ok());
+    drop(tickets);
+    assert!(queue
+        .ticket(crate::JobId(12), api("10.0.0.1"), Priority::Normal)
+        .is_ok());
+}
+
+#[tokio::test]
+async fn turn_order_test() {
+    let queue = JobQueue::new(1);
+    let alice = Some(Requester::Discord(UserId::new(1)));
+    let bob = Some(Requester::Discord(UserId::new(2)));
+    let no_updates = |_, _| async { Ok::<(), crate::Error>(()) };
+    // alice's first job has the only worker, so everyone else has to wait
+    let first = queue
+        .ticket(crate::JobId(1), alice, Priority::Normal)
+        .unwrap();
+    let running = queue.wait_with(&first, no_updates).await.unwrap();
+
+    let (ran, mut order) = tokio::sync::mpsc::unbounded_channel();
+    let mut tasks = tokio::task::JoinSet::new();
+    for (job, requester) in [(2, alice), (3, alice), (4, bob)] {
+        let ticket = queue
+            .ticket(crate::JobId(job), requester, Priority::Normal)
+            .unwrap();
+        let (line, ran) = (queue.clone(), ran.clone());
+        tasks.spawn(async move {
+            let turn = line.wait_with(&ticket, no_updates).await.unwrap();
+            ran.send(job).unwrap();
+            // hang on to the worker for a bit, nobody else should get in meanwhile
+            tokio::task::yield_now().await;
+            drop(turn);
+        });
+        // make sure they get in line in this order
+        while queue.position(crate::JobId(job)).await.is_none() {
+            tokio::task::yield_now().await;
+        }
+    }
+    drop(running);
+    while tasks.join_next().await.is_some() {}
+    drop(ran);
+
+    let mut jobs = Vec::new();
+    while let Some(job) = order.recv().await {
+        jobs.push(job);
+    }
+    // alice already had her turn, so bob goes before the rest of hers
+    assert_eq!(jobs, [4, 2, 3]);
+}
+
+#[tokio::test]
+async fn eta_test() {
+    let queue = JobQueue::new(1);
+    let user = Some(Requester::Discord(UserId::new(1)));
+    let no_updates = |_, _| async { Ok::<(), crate::Error>(()) };
+    let ticket = |job: u64, estimate: u64| {
+        let ticket = queue
+            .ticket(crate::JobId(job), user, Priority::Normal)
+            .unwrap();
+        queue.set_estimate(&ticket, Some(Duration::from_secs(estimate)));
+        ticket
+    };
+    let first = ticket(1, 60);
+    let _running = queue.wait_with(&first, no_updates).await.unwrap();
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (job, estimate) in [(2, 30), (3, 10)] {
+        let (line, ticket) = (queue.clone(), ticket(job, estimate));
+        tasks.spawn(async move { line.wait_with(&ticket, no_updates).await.map(drop) });
+        while queue.position(crate::JobId(job)).await.is_none() {
+            tokio::task::yield_now().await;
+        }
+    }
+    // the running job isn't in the way anymore, but it's still using the only worker
+    assert_eq!(queue.position(crate::JobId(2)).await, Some(1));
+    let eta = queue.eta(crate::JobId(2)).await.unwrap().as_secs();
+    assert!((59..=60).contains(&eta));
+    let eta = queue.eta(crate::JobId(3)).await.unwrap().as_secs();
+    assert!((89..=90).contains(&eta));
+    tasks.abort_all();
+}
+
+#[tokio::test]
+async fn close_test() {
+    let queue = JobQueue::new(1);
+    let user = Some(Requester::Discord(UserId::new(1)));
+    let ticket = queue
+        .ticket(crate::JobId(1), user, Priority::Normal)
+        .unwrap();
+    queue.close();
+    // nobody new gets in
+    assert!(queue
+        .ticket(crate::JobId(2), user, Priority::Normal)
+        .is_err());
+    // and nobody waiting gets a turn
+    let waited = queue
+        .wait_with(&ticket, |_, _| async { Ok::<(), crate::Error>(()) })
+        .await;
+    assert!(waited.is_err());
+    // the ticket is still out there, so it gets cancelled once time's up
+    assert_eq!(queue.drain(Duration::ZERO).await, 1);
+    assert!(ticket.control.is_cancelled());
+}
+
+#[tokio::test]
+async fn close_while_waiting_test() {
+    let queue = JobQueue::new(1);
+    let no_updates = |_, _| async { Ok::<(), crate::Error>(()) };
+    let first = queue
+        .ticket(crate::JobId(1), None, Priority::Normal)
+        .unwrap();
+    let running = queue.wait_with(&first, no_updates).await.unwrap();
+    let second = queue
+        .ticket(crate::JobId(2), None, Priority::Normal)
+        .unwrap();
+    let line = queue.clone();
+    let waiting = tokio::spawn(async move { line.wait_with(&second, no_updates).await.map(drop) });
+    while queue.position(crate::JobId(2)).await.is_none() {
+        tokio::task::yield_now().await;
+    }
+    // it's next in line for the worker, but shouldn't get it once we're shutting down
+    queue.close();
+    drop(running);
+    assert!(matches!(
+        waiting.await.unwrap(),
+        Err(ArtificeError::Restarting)
+    ));
+}
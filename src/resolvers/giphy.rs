@@ -0,0 +1,64 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use reqwest::Url;
+
+use super::{on_domain, MediaResolver};
+use crate::media_helpers::{MediaType, UrlAndMediaType};
+
+/// giphy gifs. the page links a dozen sizes, so we go straight for the original.
+pub struct Giphy;
+
+impl MediaResolver for Giphy {
+    fn name(&self) -> &'static str {
+        "giphy"
+    }
+
+    fn handles(&self, page: &Url) -> bool {
+        on_domain(page, "giphy.com")
+    }
+
+    fn resolve(&self, page: &Url, html: &str) -> Option<UrlAndMediaType> {
+        static MEDIA: OnceLock<Regex> = OnceLock::new();
+        let media = MEDIA.get_or_init(|| {
+            Regex::new(r"https://(?:media\d*|i)\.giphy\.com/media/(?:v1\.[^/]+/)?(?<id>\w+)/")
+                .unwrap()
+        });
+        // the id is on the end of the page url, ie /gifs/cat-dance-<id>,
+        // but the media links in the page are more trustworthy
+        let id = media
+            .captures(html)
+            .map(|found| found["id"].to_string())
+            .or_else(|| {
+                // anything else (search, explore, channels) doesn't end in an id
+                let mut segments = page.path_segments()?;
+                if !matches!(segments.next(), Some("gifs" | "stickers")) {
+                    return None;
+                }
+                let last = segments.next_back()?;
+                let id = last.rsplit('-').next()?;
+                (!id.is_empty()).then(|| id.to_string())
+            })?;
+        Some(UrlAndMediaType {
+            url: format!("https://i.giphy.com/media/{}/giphy.gif", id),
+            media_type: MediaType::Gif,
+            size: None,
+        })
+    }
+}
+
+#[test]
+fn giphy_test() {
+    let page = Url::parse("https://giphy.com/gifs/cat-dance-XyZ123abc").unwrap();
+    let found = Giphy.resolve(&page, &super::fixture("giphy.html")).unwrap();
+    assert_eq!(
+        found.url,
+        "https://i.giphy.com/media/Qw8Rt5Ea3lB0c/giphy.gif"
+    );
+    // no media links in the page, so the url will have to do
+    let found = Giphy.resolve(&page, "<html></html>").unwrap();
+    assert_eq!(found.url, "https://i.giphy.com/media/XyZ123abc/giphy.gif");
+    // but only if it's a gif or sticker page
+    let page = Url::parse("https://giphy.com/explore/cat-dance").unwrap();
+    assert!(Giphy.resolve(&page, "<html></html>").is_none());
+}
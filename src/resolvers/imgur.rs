@@ -0,0 +1,37 @@
+use reqwest::Url;
+
+use super::{media_from_url, meta_tags, on_domain, MediaResolver};
+use crate::media_helpers::{MediaType, UrlAndMediaType};
+
+/// imgur posts and galleries. "gifs" there are really silent mp4s.
+pub struct Imgur;
+
+impl MediaResolver for Imgur {
+    fn name(&self) -> &'static str {
+        "imgur"
+    }
+
+    fn handles(&self, page: &Url) -> bool {
+        on_domain(page, "imgur.com")
+    }
+
+    fn resolve(&self, _page: &Url, html: &str) -> Option<UrlAndMediaType> {
+        let tags = meta_tags(html);
+        let (found, default) = match tags.get("og:video") {
+            Some(video) => (video, MediaType::Video),
+            None => (tags.get("og:image")?, MediaType::Image),
+        };
+        // the query asks for a smaller, facebook sized version
+        let mut url = Url::parse(found).ok()?;
+        url.set_query(None);
+        media_from_url(url.as_str(), default)
+    }
+}
+
+#[test]
+fn imgur_test() {
+    let page = Url::parse("https://imgur.com/gallery/cat-AbC123").unwrap();
+    let found = Imgur.resolve(&page, &super::fixture("imgur.html")).unwrap();
+    assert_eq!(found.url, "https://i.imgur.com/AbC123x.mp4");
+    assert_eq!(found.media_type, MediaType::Video);
+}
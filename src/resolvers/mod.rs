@@ -0,0 +1,230 @@
+// digging the actual media out of pages that only link to it, like tenor and imgur.
+// to add a new site: make a struct that implements `MediaResolver` and put it in `RESOLVERS`,
+// somewhere before `OpenGraph`, which takes whatever nobody else wanted.
+
+mod giphy;
+mod imgur;
+mod opengraph;
+mod tenor;
+
+pub use giphy::Giphy;
+pub use imgur::Imgur;
+pub use opengraph::OpenGraph;
+pub use tenor::Tenor;
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use regex::Regex;
+use reqwest::Url;
+use tracing::info;
+
+use crate::media_helpers::{MediaType, UrlAndMediaType};
+use crate::ArtificeError;
+
+/// pages bigger than this get cut off, the bits we want are up in the head anyway
+pub const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// how long a page gets to load
+pub const PAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// every resolver, in the order they get asked
+pub static RESOLVERS: &[&dyn MediaResolver] = &[&Tenor, &Giphy, &Imgur, &OpenGraph];
+
+/// something that knows where a site keeps its media
+pub trait MediaResolver: Send + Sync {
+    /// short name for logs, ie "tenor"
+    fn name(&self) -> &'static str;
+
+    /// whether `page` is one of ours
+    fn handles(&self, page: &Url) -> bool;
+
+    /// whether this takes pages from any old site, rather than ones it knows.
+    /// those only get loaded when someone pointed us at the page.
+    fn catch_all(&self) -> bool {
+        false
+    }
+
+    /// find the media in the html of `page`.
+    /// `None` if there isn't any, and the next resolver gets a go.
+    fn resolve(&self, page: &Url, html: &str) -> Option<UrlAndMediaType>;
+}
+
+/// load `page` and ask every resolver that wants it where the media is.
+/// unless `any_site`, only sites a resolver knows get loaded,
+/// so skimming the history doesn't download every youtube page in it.
+pub async fn resolve_page(page: &str, any_site: bool) -> crate::Result<Option<UrlAndMediaType>> {
+    let Ok(url) = Url::parse(page) else {
+        return Ok(None);
+    };
+    let wanted: Vec<_> = RESOLVERS
+        .iter()
+        .copied()
+        .filter(|resolver| (any_site || !resolver.catch_all()) && resolver.handles(&url))
+        .collect();
+    if wanted.is_empty() {
+        return Ok(None);
+    }
+    let html = fetch_page(&url).await?;
+    Ok(resolve_html(&wanted, &url, &html))
+}
+
+/// the first thing any of `resolvers` finds in `html`
+fn resolve_html(
+    resolvers: &[&dyn MediaResolver],
+    page: &Url,
+    html: &str,
+) -> Option<UrlAndMediaType> {
+    resolvers.iter().find_map(|resolver| {
+        let found = resolver.resolve(page, html)?;
+        info!("{} found {}", resolver.name(), found.url);
+        Some(found)
+    })
+}
+
+/// the start of the html at `url`, up to `MAX_PAGE_BYTES`
+async fn fetch_page(url: &Url) -> crate::Result<String> {
+    let fail = |err: reqwest::Error| ArtificeError::DownloadFailed(err.to_string());
+    let url = crate::net::check(url.as_str())?;
+    let mut resp = crate::net::client()
+        .get(url)
+        .timeout(PAGE_TIMEOUT)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(fail)?;
+    let mut page = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(fail)? {
+        page.extend_from_slice(&chunk);
+        if page.len() >= MAX_PAGE_BYTES {
+            page.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&page).into_owned())
+}
+
+/// every `<meta>` tag in `html`, as what it's called (`property`, `name` or `itemprop`) to its `content`.
+/// the first one of each name wins, same as in a browser.
+pub fn meta_tags(html: &str) -> HashMap<String, String> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static ATTRIBUTE: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"(?i)<meta\s[^>]*>").unwrap());
+    let attribute =
+        ATTRIBUTE.get_or_init(|| Regex::new(r#"([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap());
+
+    let mut tags = HashMap::new();
+    for found in tag.find_iter(html) {
+        let mut name = None;
+        let mut content = None;
+        for attr in attribute.captures_iter(found.as_str()) {
+            let value = attr
+                .get(2)
+                .or(attr.get(3))
+                .map_or("", |value| value.as_str());
+            match attr[1].to_lowercase().as_str() {
+                "property" | "name" | "itemprop" => name = Some(value.to_string()),
+                "content" => content = Some(unescape(value)),
+                _ => {}
+            }
+        }
+        if let (Some(name), Some(content)) = (name, content) {
+            tags.entry(name).or_insert(content);
+        }
+    }
+    tags
+}
+
+/// undo the html escaping that shows up in urls
+fn unescape(text: &str) -> String {
+    text.replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x2F;", "/")
+}
+
+/// `url` as media, guessing the type from its extension and falling back to `default`.
+/// the file gets sniffed after downloading anyway, so close enough is fine.
+pub fn media_from_url(url: &str, default: MediaType) -> Option<UrlAndMediaType> {
+    let parsed = Url::parse(url).ok()?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return None;
+    }
+    let media_type = std::path::Path::new(parsed.path())
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(MediaType::from_extension)
+        .unwrap_or(default);
+    Some(UrlAndMediaType {
+        url: parsed.into(),
+        media_type,
+        size: None,
+    })
+}
+
+/// whether `page` is on `domain` or one of its subdomains
+fn on_domain(page: &Url, domain: &str) -> bool {
+    page.host_str().is_some_and(|host| {
+        host == domain
+            || host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.ends_with('.'))
+    })
+}
+
+/// the html saved in `src/test_files` for testing resolvers offline
+#[cfg(test)]
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!(
+        "{}/src/test_files/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+    .unwrap()
+}
+
+#[test]
+fn meta_tags_test() {
+    let tags = meta_tags(
+        r#"<head><meta property="og:image" content="https://example.com/a.png?x=1&amp;y=2">
+        <META content='second' name="twitter:card"/><meta property="og:image" content="ignored"></head>"#,
+    );
+    assert_eq!(tags["og:image"], "https://example.com/a.png?x=1&y=2");
+    assert_eq!(tags["twitter:card"], "second");
+
+    let page = Url::parse("https://media.tenor.com/x").unwrap();
+    assert!(on_domain(&page, "tenor.com"));
+    assert!(!on_domain(
+        &Url::parse("https://nottenor.com").unwrap(),
+        "tenor.com"
+    ));
+}
+
+#[tokio::test]
+async fn catch_all_test() {
+    // nobody but opengraph wants this, and we weren't pointed at it, so it isn't even loaded
+    let found = resolve_page("http://127.0.0.1:9/watch?v=abc", false).await;
+    assert!(matches!(found, Ok(None)));
+    // when we were, it's still somewhere we don't go
+    assert!(resolve_page("http://127.0.0.1:9/watch?v=abc", true)
+        .await
+        .is_err());
+}
+
+#[test]
+fn resolver_order_test() {
+    // tenor pages have opengraph tags too, but tenor knows better
+    let page = Url::parse("https://tenor.com/view/cat-dance-gif-123").unwrap();
+    let html = fixture("tenor.html");
+    let wanted: Vec<_> = RESOLVERS
+        .iter()
+        .copied()
+        .filter(|r| r.handles(&page))
+        .collect();
+    let found = resolve_html(&wanted, &page, &html).unwrap();
+    assert_eq!(
+        found.url,
+        "https://media.tenor.com/AbCdEfGhIjKAAAAC/cat-dance.gif"
+    );
+}
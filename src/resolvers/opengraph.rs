@@ -0,0 +1,72 @@
+use reqwest::Url;
+
+use super::{media_from_url, meta_tags, MediaResolver};
+use crate::media_helpers::{MediaType, UrlAndMediaType};
+
+/// any page with `og:video` or `og:image` tags, which is most of them
+pub struct OpenGraph;
+
+impl MediaResolver for OpenGraph {
+    fn name(&self) -> &'static str {
+        "opengraph"
+    }
+
+    fn handles(&self, _page: &Url) -> bool {
+        true
+    }
+
+    fn catch_all(&self) -> bool {
+        true
+    }
+
+    fn resolve(&self, _page: &Url, html: &str) -> Option<UrlAndMediaType> {
+        let tags = meta_tags(html);
+        let video = ["og:video:secure_url", "og:video:url", "og:video"]
+            .iter()
+            .find_map(|name| tags.get(*name));
+        if let Some(video) = video {
+            // youtube and friends point this at their player, which is just another page
+            let is_player = tags
+                .get("og:video:type")
+                .is_some_and(|kind| kind.starts_with("text/html"));
+            if !is_player {
+                return media_from_url(video, MediaType::Video);
+            }
+        }
+        // a video page without a video we can grab. its thumbnail isn't what anyone wanted
+        if tags
+            .get("og:type")
+            .is_some_and(|kind| kind.starts_with("video"))
+        {
+            return None;
+        }
+        let image = ["og:image:secure_url", "og:image:url", "og:image"]
+            .iter()
+            .find_map(|name| tags.get(*name))?;
+        media_from_url(image, MediaType::Image)
+    }
+}
+
+#[test]
+fn opengraph_test() {
+    let page = Url::parse("https://example.com/post/1").unwrap();
+    let found = OpenGraph
+        .resolve(&page, &super::fixture("opengraph.html"))
+        .unwrap();
+    assert_eq!(found.url, "https://cdn.example.com/clips/funny.mp4");
+    assert_eq!(found.media_type, MediaType::Video);
+
+    // only a player and a thumbnail, so nothing for us
+    let page = Url::parse("https://www.youtube.com/watch?v=abc").unwrap();
+    assert!(OpenGraph
+        .resolve(&page, &super::fixture("youtube.html"))
+        .is_none());
+
+    let found = OpenGraph
+        .resolve(
+            &page,
+            r#"<meta property="og:image" content="https://example.com/pic.jpg">"#,
+        )
+        .unwrap();
+    assert_eq!(found.media_type, MediaType::Image);
+}
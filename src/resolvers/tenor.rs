@@ -0,0 +1,36 @@
+use reqwest::Url;
+
+use super::{media_from_url, meta_tags, on_domain, MediaResolver};
+use crate::media_helpers::{MediaType, UrlAndMediaType};
+
+/// tenor gifs, which discord embeds as "gifv" pages
+pub struct Tenor;
+
+impl MediaResolver for Tenor {
+    fn name(&self) -> &'static str {
+        "tenor"
+    }
+
+    fn handles(&self, page: &Url) -> bool {
+        on_domain(page, "tenor.com")
+    }
+
+    fn resolve(&self, _page: &Url, html: &str) -> Option<UrlAndMediaType> {
+        // the gif itself is in a microdata tag, with its slashes escaped
+        let html = html.replace("\\u002F", "/");
+        let tags = meta_tags(&html);
+        media_from_url(tags.get("contentUrl")?, MediaType::Gif)
+    }
+}
+
+#[test]
+fn tenor_test() {
+    let page = Url::parse("https://tenor.com/view/cat-dance-gif-123").unwrap();
+    let found = Tenor.resolve(&page, &super::fixture("tenor.html")).unwrap();
+    assert_eq!(
+        found.url,
+        "https://media.tenor.com/AbCdEfGhIjKAAAAC/cat-dance.gif"
+    );
+    assert_eq!(found.media_type, MediaType::Gif);
+    assert!(Tenor.resolve(&page, "<html></html>").is_none());
+}
@@ -0,0 +1,289 @@
+// working out what a file really is, instead of taking discord's word for it.
+//
+// the first few bytes narrow it down (and tell animated webp/png apart from still ones),
+// then ffprobe settles whether there's anything moving, anything to look at, or just sound.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::media_helpers::MediaType;
+use crate::probe::{probe, MediaInfo};
+use crate::ArtificeError;
+
+/// how much of a file we need to see to recognise it
+pub const SNIFF_LEN: usize = 64;
+
+/// what the start of a file says it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sniffed {
+    /// animated images come back as `Gif`, since that's how we treat them
+    pub media_type: MediaType,
+    /// what the file should be called, so ffmpeg knows what to write back out
+    pub extension: &'static str,
+}
+
+impl Sniffed {
+    fn new(media_type: MediaType, extension: &'static str) -> Option<Sniffed> {
+        Some(Sniffed {
+            media_type,
+            extension,
+        })
+    }
+}
+
+/// recognise a file from its first bytes, `SNIFF_LEN` of them is plenty
+pub fn sniff(header: &[u8]) -> Option<Sniffed> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    if at(0, b"\x89PNG\r\n\x1a\n") {
+        let animated =
+            png_animated(|offset| header.get(offset as usize..)?.get(..8)?.try_into().ok());
+        return png(animated);
+    }
+    if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        return Sniffed::new(MediaType::Gif, "gif");
+    }
+    if at(0, b"\xff\xd8\xff") {
+        return Sniffed::new(MediaType::Image, "jpg");
+    }
+    if at(0, b"RIFF") {
+        if at(8, b"WEBP") {
+            // extended webp has a flags byte, and one of the flags is "this moves"
+            let animated = at(12, b"VP8X") && header.get(20).is_some_and(|flags| flags & 0x02 != 0);
+            return if animated {
+                Sniffed::new(MediaType::Gif, "webp")
+            } else {
+                Sniffed::new(MediaType::Image, "webp")
+            };
+        }
+        if at(8, b"WAVE") {
+            return Sniffed::new(MediaType::Audio, "wav");
+        }
+        if at(8, b"AVI ") {
+            return Sniffed::new(MediaType::Video, "avi");
+        }
+        return None;
+    }
+    if at(0, b"BM") && header.len() >= 14 {
+        return Sniffed::new(MediaType::Image, "bmp");
+    }
+    if at(0, b"II*\0") || at(0, b"MM\0*") {
+        return Sniffed::new(MediaType::Image, "tiff");
+    }
+    if at(4, b"ftyp") {
+        // the brand says what sort of iso media file it is
+        return match header.get(8..12) {
+            Some(b"heic" | b"heix" | b"mif1") => Sniffed::new(MediaType::Image, "heic"),
+            Some(b"avif") => Sniffed::new(MediaType::Image, "avif"),
+            Some(b"M4A " | b"M4B ") => Sniffed::new(MediaType::Audio, "m4a"),
+            Some(b"qt  ") => Sniffed::new(MediaType::Video, "mov"),
+            _ => Sniffed::new(MediaType::Video, "mp4"),
+        };
+    }
+    if at(0, b"\x1a\x45\xdf\xa3") {
+        return if find(header, b"webm").is_some() {
+            Sniffed::new(MediaType::Video, "webm")
+        } else {
+            Sniffed::new(MediaType::Video, "mkv")
+        };
+    }
+    if at(0, b"fLaC") {
+        return Sniffed::new(MediaType::Audio, "flac");
+    }
+    if at(0, b"OggS") {
+        // could be theora video, but it almost never is. ffprobe gets the final say anyway
+        return Sniffed::new(MediaType::Audio, "ogg");
+    }
+    if at(0, b"ID3") || (header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0) {
+        return Sniffed::new(MediaType::Audio, "mp3");
+    }
+    None
+}
+
+fn png(animated: bool) -> Option<Sniffed> {
+    if animated {
+        Sniffed::new(MediaType::Gif, "png")
+    } else {
+        Sniffed::new(MediaType::Image, "png")
+    }
+}
+
+/// animated pngs have an `acTL` chunk somewhere before the first image data.
+/// `chunk_at` gives back the length and name (8 bytes) of the chunk starting at an offset,
+/// or `None` once there's nothing more to read.
+fn png_animated(mut chunk_at: impl FnMut(u64) -> Option<[u8; 8]>) -> bool {
+    // chunks start right after the signature
+    let mut offset = 8;
+    while let Some(chunk) = chunk_at(offset) {
+        match &chunk[4..] {
+            b"acTL" => return true,
+            b"IDAT" => return false,
+            _ => {
+                let length = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                // length and name up front, crc at the end
+                offset += 12 + u64::from(length);
+            }
+        }
+    }
+    false
+}
+
+/// where `needle` first shows up in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// read the start of `path` and `sniff` it
+pub fn sniff_file(path: &Path) -> crate::Result<Option<Sniffed>> {
+    let mut file = std::fs::File::open(path)?;
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    (&mut file)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    let sniffed = sniff(&header);
+    if sniffed.is_some_and(|sniffed| sniffed.extension == "png") {
+        // color profiles and text can push the animation chunk way past the header,
+        // so walk the chunks in the file itself
+        return Ok(png(png_animated(|offset| {
+            let mut chunk = [0; 8];
+            file.seek(SeekFrom::Start(offset)).ok()?;
+            file.read_exact(&mut chunk).ok()?;
+            Some(chunk)
+        })));
+    }
+    Ok(sniffed)
+}
+
+/// settle on a media type from what the bytes said and what ffprobe found
+pub fn classify(sniffed: Option<Sniffed>, info: &MediaInfo) -> MediaType {
+    let Ok(video) = info.require_video() else {
+        // a song with its album art is still a song
+        return if info.audio.is_some() {
+            MediaType::Audio
+        } else {
+            MediaType::Unknown
+        };
+    };
+    // apngs and animated webps say so up front, even when ffprobe reads them as a still.
+    // every gif sniffs as `Gif` though, so those are left to ffprobe to count frames
+    let said_animated = sniffed
+        .is_some_and(|sniffed| sniffed.media_type == MediaType::Gif && sniffed.extension != "gif");
+    if !info.is_animated() && !said_animated {
+        return MediaType::Image;
+    }
+    let gif_like = sniffed.is_some_and(|sniffed| sniffed.media_type == MediaType::Gif)
+        || matches!(video.codec.as_str(), "gif" | "apng" | "webp");
+    if gif_like {
+        MediaType::Gif
+    } else {
+        MediaType::Video
+    }
+}
+
+/// work out what the file at `path` really is. blocks on ffprobe.
+pub fn identify(path: &Path) -> crate::Result<MediaType> {
+    let sniffed = sniff_file(path)?;
+    let info = probe(path)?;
+    match classify(sniffed, &info) {
+        MediaType::Unknown => Err(ArtificeError::Unsupported(
+            "That doesn't look like anything we can work with.".into(),
+        )),
+        media_type => Ok(media_type),
+    }
+}
+
+#[test]
+fn sniff_test() {
+    let sniffed_type = |header: &[u8]| sniff(header).map(|sniffed| sniffed.media_type);
+
+    assert_eq!(
+        sniffed_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+        Some(MediaType::Image)
+    );
+    assert_eq!(
+        sniffed_type(&png_with(&[b"acTL", b"IDAT"], 8)),
+        Some(MediaType::Gif)
+    );
+    // other chunks can come before the animation one
+    let apng = png_with(&[b"gAMA", b"acTL", b"IDAT"], 4);
+    assert_eq!(sniffed_type(&apng), Some(MediaType::Gif));
+    // but it doesn't count after the image data
+    assert_eq!(
+        sniffed_type(&png_with(&[b"IDAT", b"acTL"], 4)),
+        Some(MediaType::Image)
+    );
+    assert_eq!(sniffed_type(b"GIF89a\x01\0\x01\0"), Some(MediaType::Gif));
+    assert_eq!(
+        sniffed_type(b"\xff\xd8\xff\xe0\0\x10JFIF"),
+        Some(MediaType::Image)
+    );
+
+    let mut webp = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+    webp.extend([0x00, 0, 0, 0]);
+    assert_eq!(sniffed_type(&webp), Some(MediaType::Image));
+    webp[20] = 0x02;
+    assert_eq!(sniffed_type(&webp), Some(MediaType::Gif));
+
+    assert_eq!(
+        sniffed_type(b"\0\0\0\x20ftypisom\0\0\x02\0"),
+        Some(MediaType::Video)
+    );
+    assert_eq!(
+        sniffed_type(b"\0\0\0\x20ftypM4A \0\0\x02\0"),
+        Some(MediaType::Audio)
+    );
+    assert_eq!(sniffed_type(b"ID3\x04\0\0\0\0\0\0"), Some(MediaType::Audio));
+    assert_eq!(sniffed_type(b"<!DOCTYPE html>"), None);
+    assert_eq!(sniffed_type(b""), None);
+}
+
+/// a png with empty-ish chunks called `names` after the header, each holding `size` bytes
+#[cfg(test)]
+fn png_with(names: &[&[u8; 4]], size: usize) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for name in [b"IHDR"].into_iter().chain(names.iter().copied()) {
+        png.extend((size as u32).to_be_bytes());
+        png.extend(name);
+        png.extend(vec![0; size]);
+        // crc
+        png.extend([0; 4]);
+    }
+    png
+}
+
+#[test]
+fn sniff_file_test() {
+    // a color profile big enough to push the animation chunk out of the header
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.png");
+    let apng = png_with(&[b"iCCP", b"acTL", b"IDAT"], 500);
+    std::fs::write(&path, &apng).unwrap();
+    assert_eq!(
+        sniff(&apng[..SNIFF_LEN]).map(|sniffed| sniffed.media_type),
+        Some(MediaType::Image)
+    );
+    assert_eq!(
+        sniff_file(&path).unwrap().map(|sniffed| sniffed.media_type),
+        Some(MediaType::Gif)
+    );
+
+    let still = png_with(&[b"iCCP", b"IDAT"], 500);
+    std::fs::write(&path, still).unwrap();
+    assert_eq!(
+        sniff_file(&path).unwrap().map(|sniffed| sniffed.media_type),
+        Some(MediaType::Image)
+    );
+}
+
+#[test]
+fn identify_test() {
+    ffmpeg_sidecar::download::auto_download().unwrap();
+    let srcpath = env!("CARGO_MANIFEST_DIR");
+    let identified =
+        |file: &str| identify(Path::new(&format!("{}/src/test_files/{}", srcpath, file))).unwrap();
+    assert_eq!(identified("bajacat.png"), MediaType::Image);
+    assert_eq!(identified("CC0-jazz-guitar.mp3"), MediaType::Audio);
+    assert_eq!(identified("text-video-test.mp4"), MediaType::Video);
+}
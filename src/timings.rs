@@ -0,0 +1,122 @@
+// keeps track of how long operations usually take, so we can tell people how long they'll wait.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::job::JobPart;
+use crate::media_helpers::MediaType;
+
+/// how much each new measurement moves the average
+const SMOOTHING: f64 = 0.3;
+/// files smaller than this count as this big, so tiny files don't throw off the per-megabyte rate
+const MIN_MEGABYTES: f64 = 0.1;
+/// what we guess when there's no size to go on
+const UNKNOWN_MEGABYTES: f64 = 2.0;
+
+/// running average of how long one operation takes on one kind of media
+#[derive(Debug, Clone, Copy)]
+struct Average {
+    secs_per_megabyte: f64,
+}
+
+type Key = (&'static str, MediaType);
+
+#[derive(Default)]
+pub struct Timings {
+    averages: std::sync::Mutex<HashMap<Key, Average>>,
+}
+
+fn megabytes(size: Option<u64>) -> f64 {
+    size.map(|bytes| bytes as f64 / 1_000_000.0)
+        .unwrap_or(UNKNOWN_MEGABYTES)
+        .max(MIN_MEGABYTES)
+}
+
+/// the operation name and media type of every step in `part`
+fn keys(part: &JobPart, media_type: MediaType) -> Vec<Key> {
+    let mut media_type = media_type;
+    part.subparts
+        .iter()
+        .map(|job| {
+            let operation = job.operation();
+            let key = (operation.name(), media_type);
+            media_type = operation.output_type(media_type);
+            key
+        })
+        .collect()
+}
+
+impl Timings {
+    /// how long `part` will probably take on a file of `size` bytes.
+    /// None if we haven't seen every one of its operations run yet.
+    pub fn estimate(&self, part: &JobPart, media_type: MediaType, size: Option<u64>) -> Option<Duration> {
+        let averages = self.averages.lock().ok()?;
+        let megabytes = megabytes(size);
+        let mut secs = 0.0;
+        for key in keys(part, media_type) {
+            secs += averages.get(&key)?.secs_per_megabyte * megabytes;
+        }
+        Some(Duration::from_secs_f64(secs))
+    }
+
+    /// remember that `part` took `took` on a file of `size` bytes.
+    /// the time is split between the steps by how long we already thought they'd take.
+    pub fn record(&self, part: &JobPart, media_type: MediaType, size: Option<u64>, took: Duration) {
+        let Ok(mut averages) = self.averages.lock() else {
+            return;
+        };
+        let keys = keys(part, media_type);
+        if keys.is_empty() {
+            return;
+        }
+        let megabytes = megabytes(size);
+        let rate = took.as_secs_f64() / megabytes;
+
+        // what we thought each step cost, steps we know nothing about get an even share
+        let known: Vec<Option<f64>> = keys
+            .iter()
+            .map(|key| averages.get(key).map(|average| average.secs_per_megabyte))
+            .collect();
+        let fallback = rate / keys.len() as f64;
+        let weights: Vec<f64> = known.iter().map(|w| w.unwrap_or(fallback)).collect();
+        let total: f64 = weights.iter().sum();
+
+        for (key, weight) in keys.into_iter().zip(weights) {
+            let share = if total > 0.0 {
+                rate * weight / total
+            } else {
+                fallback
+            };
+            averages
+                .entry(key)
+                .and_modify(|average| {
+                    average.secs_per_megabyte += SMOOTHING * (share - average.secs_per_megabyte);
+                })
+                .or_insert(Average {
+                    secs_per_megabyte: share,
+                });
+        }
+    }
+}
+
+#[test]
+fn timings_test() {
+    let timings = Timings::default();
+    let resize: JobPart = "resize 256".parse().unwrap();
+    let chain: JobPart = "resize 256 | rotate 90".parse().unwrap();
+
+    // nothing to go on yet
+    assert_eq!(timings.estimate(&resize, MediaType::Video, Some(1_000_000)), None);
+
+    // 4 seconds for a megabyte, so 8 for two
+    timings.record(&resize, MediaType::Video, Some(1_000_000), Duration::from_secs(4));
+    let estimate = timings.estimate(&resize, MediaType::Video, Some(2_000_000)).unwrap();
+    assert_eq!(estimate.as_secs(), 8);
+    // other media types are tracked separately
+    assert_eq!(timings.estimate(&resize, MediaType::Image, Some(1_000_000)), None);
+
+    // rotate is still a mystery, so the chain can't be guessed
+    assert_eq!(timings.estimate(&chain, MediaType::Video, Some(1_000_000)), None);
+    timings.record(&chain, MediaType::Video, Some(1_000_000), Duration::from_secs(6));
+    assert!(timings.estimate(&chain, MediaType::Video, Some(1_000_000)).is_some());
+}